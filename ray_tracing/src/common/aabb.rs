@@ -15,11 +15,27 @@ impl AABB {
         Self { minimum, maximum }
     }
 
-    pub fn hit(&self, ray: &Ray, mut t_min: f32, mut t_max: f32) -> bool {
+    pub fn centroid(&self) -> Point3 {
+        (self.minimum + self.maximum) * 0.5
+    }
+
+    pub fn surface_area(&self) -> f32 {
+        let d = self.maximum - self.minimum;
+        2.0 * (d.x * d.y + d.y * d.z + d.z * d.x)
+    }
+
+    pub fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> bool {
+        let inv_dir = Vec3::new(1.0 / ray.dir.x, 1.0 / ray.dir.y, 1.0 / ray.dir.z);
+        self.hit_inverse(ray.orig, inv_dir, t_min, t_max)
+    }
+
+    /// Slab test with the reciprocal of the ray direction, computed once per ray
+    /// by callers testing many boxes.
+    pub fn hit_inverse(&self, orig: Point3, inv_dir: Vec3, mut t_min: f32, mut t_max: f32) -> bool {
         for a in 0..3 {
-            let inv_d = 1.0 / ray.dir[a];
-            let mut t0 = (self.minimum[a] - ray.orig[a]) * inv_d;
-            let mut t1 = (self.maximum[a] - ray.orig[a]) * inv_d;
+            let inv_d = inv_dir[a];
+            let mut t0 = (self.minimum[a] - orig[a]) * inv_d;
+            let mut t1 = (self.maximum[a] - orig[a]) * inv_d;
             if inv_d < 0.0 {
                 mem::swap(&mut t0, &mut t1);
             }
@@ -0,0 +1,105 @@
+use serde::Deserialize;
+
+use super::checkpoint::*;
+use super::framebuffer::*;
+use super::tone_mapping::*;
+use super::vec3::*;
+
+/// Stops sampling pixels once their noise is low enough, so the sample budget goes
+/// where the image is still noisy.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct AdaptiveSettings {
+    /// Noise left in a pixel, roughly in display units where one is the range of a
+    /// channel, below which the pixel stops being sampled.
+    pub threshold: f32,
+    /// Samples every pixel takes before its noise is trusted, and added by every
+    /// pass of a render that isn't progressive.
+    pub min_samples: u32,
+    /// Where the map of the samples taken goes, next to the image by default.
+    pub heatmap: Option<String>,
+}
+
+impl Default for AdaptiveSettings {
+    fn default() -> Self {
+        Self {
+            threshold: 0.01,
+            min_samples: 16,
+            heatmap: None,
+        }
+    }
+}
+
+/// Noise of a pixel as it shows on screen. The sRGB curve is close to a square root,
+/// which stretches the error of a pixel by about half the inverse square root of
+/// its brightness.
+pub fn pixel_noise(pixel: &PixelSamples) -> f32 {
+    let mean = luminance(pixel.mean()).max(1.0e-4);
+
+    pixel.standard_error() / (2.0 * mean.sqrt())
+}
+
+/// Pixels that still need samples: noisy ones and their neighbours, since a pixel
+/// whose few samples all missed a small light looks converged while the pixel next
+/// to it doesn't.
+pub fn active_pixels(state: &Checkpoint, threshold: f32, samples_per_pixel: u32) -> Vec<bool> {
+    let (width, height) = (state.width as i64, state.height as i64);
+    let noisy: Vec<bool> = state
+        .pixels
+        .iter()
+        .map(|pixel| pixel_noise(pixel) > threshold)
+        .collect();
+
+    (0..height)
+        .flat_map(|y| (0..width).map(move |x| (x, y)))
+        .map(|(x, y)| {
+            let i = (y * width + x) as usize;
+            if state.pixels[i].count >= samples_per_pixel {
+                return false;
+            }
+
+            (y - 1..=y + 1).any(|ny| {
+                (x - 1..=x + 1).any(|nx| {
+                    nx >= 0
+                        && nx < width
+                        && ny >= 0
+                        && ny < height
+                        && noisy[(ny * width + nx) as usize]
+                })
+            })
+        })
+        .collect()
+}
+
+/// Samples taken in every pixel, from black for none through red to white for
+/// `samples_per_pixel`.
+pub fn sample_heatmap(state: &Checkpoint, samples_per_pixel: u32) -> Framebuffer {
+    const STOPS: [(f32, f32, f32); 5] = [
+        (0.0, 0.0, 0.0),
+        (0.2, 0.1, 0.5),
+        (0.8, 0.2, 0.3),
+        (1.0, 0.6, 0.0),
+        (1.0, 1.0, 0.8),
+    ];
+
+    let pixels = state
+        .pixels
+        .iter()
+        .map(|pixel| {
+            let t = (pixel.count as f32 / samples_per_pixel.max(1) as f32).clamp(0.0, 1.0);
+            let position = t * (STOPS.len() - 1) as f32;
+            let i = (position as usize).min(STOPS.len() - 2);
+            let f = position - i as f32;
+            let (a, b) = (STOPS[i], STOPS[i + 1]);
+
+            // blended in sRGB, where the steps look even
+            Color::new(
+                srgb_to_linear(a.0 + f * (b.0 - a.0)),
+                srgb_to_linear(a.1 + f * (b.1 - a.1)),
+                srgb_to_linear(a.2 + f * (b.2 - a.2)),
+            )
+        })
+        .collect();
+
+    Framebuffer::from_pixels(state.width, state.height, pixels)
+}
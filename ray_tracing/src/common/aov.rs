@@ -0,0 +1,183 @@
+use serde::Deserialize;
+
+use super::framebuffer::*;
+use super::tone_mapping::*;
+use super::vec2::*;
+use super::vec3::*;
+
+/// Camera rays per pixel the first hit features are averaged over, recorded from
+/// the first samples every pixel takes in a render so they line up with it.
+pub const FEATURE_SAMPLES: u32 = 16;
+
+/// Extra image written next to the render, named after it like `cornell.albedo.png`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Aov {
+    /// Color of the first surface hit: what it reflects, or its light.
+    Albedo,
+    /// Shading normal of the first surface hit in world space, facing the camera.
+    Normal,
+    /// Distance from the camera to the first surface hit, averaged over the rays that
+    /// hit something, zero where none did.
+    Depth,
+    /// Texture coordinates of the first surface hit.
+    Uv,
+    /// Material hit first, numbered from one in the order they show up in the image.
+    MaterialId,
+    /// Top level object hit first, numbered from one in the order of the scene.
+    ObjectId,
+    /// Light seen directly or after bouncing off the first surface.
+    Direct,
+    /// Light that bounced off two surfaces or more.
+    Indirect,
+}
+
+impl Aov {
+    pub const ALL: [Aov; 8] = [
+        Aov::Albedo,
+        Aov::Normal,
+        Aov::Depth,
+        Aov::Uv,
+        Aov::MaterialId,
+        Aov::ObjectId,
+        Aov::Direct,
+        Aov::Indirect,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Aov::Albedo => "albedo",
+            Aov::Normal => "normal",
+            Aov::Depth => "depth",
+            Aov::Uv => "uv",
+            Aov::MaterialId => "material_id",
+            Aov::ObjectId => "object_id",
+            Aov::Direct => "direct",
+            Aov::Indirect => "indirect",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Aov> {
+        Aov::ALL.iter().copied().find(|aov| aov.name() == name)
+    }
+
+    /// Where the output goes for an image saved to `path`.
+    pub fn path(&self, path: &str) -> String {
+        companion_path(path, self.name())
+    }
+
+    /// Whether the output holds light, saved like the render itself. The others are
+    /// saved as they are to the float formats and as a preview to the others.
+    pub fn is_light(&self) -> bool {
+        matches!(self, Aov::Direct | Aov::Indirect)
+    }
+}
+
+/// What the camera rays of a pixel hit first.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct PixelFeatures {
+    pub albedo: Color,
+    pub normal: Vec3,
+    pub depth: f32,
+    pub uv: Vec2,
+    pub material_id: u32,
+    pub object_id: u32,
+}
+
+/// First hit features of every pixel, averaged over its first camera rays. IDs
+/// can't be averaged and come from the first ray alone.
+#[derive(Debug, Clone)]
+pub struct Features {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<PixelFeatures>,
+}
+
+impl Features {
+    /// Values of `aov` as they are, depth and IDs in every channel. Panics for the
+    /// light outputs, which aren't features.
+    pub fn framebuffer(&self, aov: Aov) -> Framebuffer {
+        let pixels = self
+            .pixels
+            .iter()
+            .map(|pixel| match aov {
+                Aov::Albedo => pixel.albedo,
+                Aov::Normal => pixel.normal,
+                Aov::Depth => Color::new(pixel.depth, pixel.depth, pixel.depth),
+                Aov::Uv => Color::new(pixel.uv.x, pixel.uv.y, 0.0),
+                Aov::MaterialId => id_value(pixel.material_id),
+                Aov::ObjectId => id_value(pixel.object_id),
+                Aov::Direct | Aov::Indirect => panic!("{} isn't a feature", aov.name()),
+            })
+            .collect();
+
+        Framebuffer::from_pixels(self.width, self.height, pixels)
+    }
+
+    /// `aov` made viewable in 8 and 16 bit images: normals and texture coordinates
+    /// as colors, depth from white up close to black far away and IDs as random
+    /// colors. Values are stored linear, the sRGB encoding of the image undoes it.
+    /// Panics for the light outputs like `framebuffer`.
+    pub fn preview(&self, aov: Aov) -> Framebuffer {
+        if aov == Aov::Albedo || aov.is_light() {
+            return self.framebuffer(aov);
+        }
+
+        let (near, far) = self
+            .pixels
+            .iter()
+            .filter(|pixel| pixel.depth > 0.0)
+            .fold((f32::INFINITY, 0.0_f32), |(near, far), pixel| {
+                (near.min(pixel.depth), far.max(pixel.depth))
+            });
+        let encoded = |r: f32, g: f32, b: f32| {
+            Color::new(
+                srgb_to_linear(r.clamp(0.0, 1.0)),
+                srgb_to_linear(g.clamp(0.0, 1.0)),
+                srgb_to_linear(b.clamp(0.0, 1.0)),
+            )
+        };
+
+        let pixels = self
+            .pixels
+            .iter()
+            .map(|pixel| match aov {
+                Aov::Normal => {
+                    let n = 0.5 * pixel.normal + Vec3::new(0.5, 0.5, 0.5);
+                    encoded(n.x, n.y, n.z)
+                }
+                Aov::Depth if pixel.depth > 0.0 => {
+                    let v = 1.0 - 0.9 * (pixel.depth - near) / (far - near).max(f32::EPSILON);
+                    encoded(v, v, v)
+                }
+                Aov::Depth => Color::zero(),
+                Aov::Uv => encoded(pixel.uv.x, pixel.uv.y, 0.0),
+                Aov::MaterialId => id_color(pixel.material_id),
+                Aov::ObjectId => id_color(pixel.object_id),
+                Aov::Albedo | Aov::Direct | Aov::Indirect => unreachable!(),
+            })
+            .collect();
+
+        Framebuffer::from_pixels(self.width, self.height, pixels)
+    }
+}
+
+fn id_value(id: u32) -> Color {
+    Color::new(id as f32, id as f32, id as f32)
+}
+
+/// A color that tells an ID apart from its neighbours, black for none.
+fn id_color(id: u32) -> Color {
+    if id == 0 {
+        return Color::zero();
+    }
+
+    let mut h = id.wrapping_mul(0x9e37_79b9);
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x85eb_ca6b);
+    h ^= h >> 13;
+
+    let channel = |shift: u32| srgb_to_linear(0.2 + 0.8 * ((h >> shift) & 0xff) as f32 / 255.0);
+
+    Color::new(channel(0), channel(8), channel(16))
+}
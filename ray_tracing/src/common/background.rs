@@ -0,0 +1,26 @@
+use super::ray::*;
+use super::vec3::*;
+
+#[derive(Debug, Copy, Clone)]
+pub enum Background {
+    Color(Color),
+    Sky { horizon: Color, zenith: Color },
+}
+
+impl Background {
+    pub fn value(&self, ray: &Ray) -> Color {
+        match self {
+            Background::Color(color) => *color,
+            Background::Sky { horizon, zenith } => {
+                let t = 0.5 * (ray.dir.unit_vector().y + 1.0);
+                (1.0 - t) * *horizon + t * *zenith
+            }
+        }
+    }
+}
+
+impl Default for Background {
+    fn default() -> Self {
+        Background::Color(Color::zero())
+    }
+}
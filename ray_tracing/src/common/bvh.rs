@@ -1,11 +1,49 @@
 use std::cmp::Ordering;
 use std::sync::Arc;
 
-use rand::prelude::*;
-
 use super::aabb::*;
 use super::hittable::*;
+use super::hittable_list::*;
 use super::ray::*;
+use super::sampler::*;
+use super::vec3::*;
+
+/// Cost of visiting a node relative to intersecting a primitive, used by the
+/// surface area heuristic.
+const TRAVERSAL_COST: f32 = 0.125;
+const INTERSECTION_COST: f32 = 1.0;
+
+/// Deeper nodes are split in halves, so no tree is taller than this plus the
+/// log2 of its primitive count.
+const MAX_SAH_DEPTH: usize = 32;
+
+#[derive(Debug, Copy, Clone)]
+pub struct BVHOptions {
+    /// Maximum number of primitives in a leaf. Smaller leaves are made whenever
+    /// splitting is cheaper.
+    pub leaf_size: usize,
+    /// Number of buckets the centroids are binned into along each axis.
+    pub bins: usize,
+}
+
+impl Default for BVHOptions {
+    fn default() -> Self {
+        Self {
+            leaf_size: 4,
+            bins: 16,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default)]
+pub struct BVHStats {
+    pub depth: usize,
+    pub node_count: usize,
+    pub leaf_count: usize,
+    pub primitive_count: usize,
+    /// Expected cost of a random ray through the tree, in primitive intersections.
+    pub sah_cost: f32,
+}
 
 pub struct BVHNode {
     pub left: Arc<dyn Hittable>,
@@ -15,68 +53,55 @@ pub struct BVHNode {
 
 impl BVHNode {
     pub fn new(objects: &[Arc<dyn Hittable>], time0: f32, time1: f32) -> Self {
-        let axis: usize = rand::thread_rng().gen_range(0..3);
+        Self::build(objects, time0, time1, &BVHOptions::default()).0
+    }
 
-        let size = objects.len();
-        let left;
-        let right;
+    /// Builds the tree with a binned surface area heuristic. The result only
+    /// depends on the objects and their order.
+    pub fn build(
+        objects: &[Arc<dyn Hittable>],
+        time0: f32,
+        time1: f32,
+        options: &BVHOptions,
+    ) -> (Self, BVHStats) {
+        let tree = BuildNode::new(objects, time0, time1, options);
+        let stats = tree.stats();
 
-        match size {
-            1 => {
-                left = objects[0].clone();
-                right = objects[0].clone();
-            }
-            2 => match box_compare(&objects[0], &objects[1], axis) {
-                Ordering::Greater => {
-                    left = objects[0].clone();
-                    right = objects[1].clone();
-                }
-                _ => {
-                    left = objects[1].clone();
-                    right = objects[0].clone();
-                }
+        let node = match tree {
+            BuildNode::Interior {
+                aabb, left, right, ..
+            } => Self {
+                left: left.into_hittable(objects),
+                right: right.into_hittable(objects),
+                aabb,
+            },
+            leaf => Self {
+                aabb: leaf.aabb(),
+                left: leaf.into_hittable(objects),
+                right: Arc::new(HittableList::new()),
             },
-            _ => {
-                let mut sorted = objects
-                    .iter()
-                    .map(|a| a.clone())
-                    .collect::<Vec<Arc<dyn Hittable>>>();
-
-                sorted.sort_by(|a, b| box_compare(a, b, axis));
-
-                let mid = size / 2;
-                let start = 0;
-
-                left = Arc::new(BVHNode::new(&sorted[start..mid], time0, time1));
-                right = Arc::new(BVHNode::new(&sorted[mid..size], time0, time1));
-            }
         };
 
-        let mut box_left = AABB::default();
-        let mut box_right = AABB::default();
-
-        let has_box_a = left.bounding_box(time0, time1, &mut box_left);
-        let has_box_b = right.bounding_box(time0, time1, &mut box_right);
-
-        if !has_box_a || !has_box_b {
-            panic!("No bounding box in bvh_node constructor")
-        }
-
-        let aabb = box_left & box_right;
-
-        Self { left, right, aabb }
+        (node, stats)
     }
 }
 
 impl Hittable for BVHNode {
-    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32, record: &mut HitRecord) -> bool {
+    fn hit(
+        &self,
+        ray: &Ray,
+        t_min: f32,
+        t_max: f32,
+        record: &mut HitRecord,
+        sampler: &mut dyn Sampler,
+    ) -> bool {
         if !self.aabb.hit(ray, t_min, t_max) {
             return false;
         }
 
-        let hit_left = self.left.hit(ray, t_min, t_max, record);
+        let hit_left = self.left.hit(ray, t_min, t_max, record, sampler);
         let t_max_right = if hit_left { record.t } else { t_max };
-        let hit_right = self.right.hit(ray, t_min, t_max_right, record);
+        let hit_right = self.right.hit(ray, t_min, t_max_right, record, sampler);
 
         hit_left || hit_right
     }
@@ -88,20 +113,294 @@ impl Hittable for BVHNode {
     }
 }
 
-fn box_compare(a: &Arc<dyn Hittable>, b: &Arc<dyn Hittable>, axis: usize) -> Ordering {
-    let mut box_a = AABB::default();
-    let mut box_b = AABB::default();
+/// Intermediate tree produced by the builder, leaves index into the objects.
+pub enum BuildNode {
+    Leaf {
+        aabb: AABB,
+        objects: Vec<usize>,
+    },
+    Interior {
+        aabb: AABB,
+        /// Axis the children were split along.
+        axis: usize,
+        left: Box<BuildNode>,
+        right: Box<BuildNode>,
+    },
+}
+
+impl BuildNode {
+    pub fn new(
+        objects: &[Arc<dyn Hittable>],
+        time0: f32,
+        time1: f32,
+        options: &BVHOptions,
+    ) -> Self {
+        if objects.is_empty() {
+            panic!("No objects in bvh_node constructor");
+        }
+
+        let boxes: Vec<AABB> = objects
+            .iter()
+            .map(|object| {
+                let mut aabb = AABB::default();
+                if !object.bounding_box(time0, time1, &mut aabb) {
+                    panic!("No bounding box in bvh_node constructor");
+                }
+                aabb
+            })
+            .collect();
+
+        let builder = Builder {
+            centroids: boxes.iter().map(|b| b.centroid()).collect(),
+            boxes,
+            leaf_size: options.leaf_size.max(1),
+            bins: options.bins.max(2),
+        };
+
+        builder.node((0..objects.len()).collect(), 0)
+    }
+
+    pub fn aabb(&self) -> AABB {
+        match self {
+            BuildNode::Leaf { aabb, .. } => *aabb,
+            BuildNode::Interior { aabb, .. } => *aabb,
+        }
+    }
+
+    pub fn stats(&self) -> BVHStats {
+        let mut stats = BVHStats::default();
+        let root_area = self.aabb().surface_area();
+        self.collect_stats(1, root_area, &mut stats);
+        stats
+    }
+
+    fn collect_stats(&self, depth: usize, root_area: f32, stats: &mut BVHStats) {
+        let area = self.aabb().surface_area();
+        // degenerate roots, like a single flat triangle, are always hit
+        let probability = if root_area > 0.0 {
+            area / root_area
+        } else {
+            1.0
+        };
+
+        stats.depth = stats.depth.max(depth);
+        stats.node_count += 1;
+
+        match self {
+            BuildNode::Leaf { objects, .. } => {
+                stats.leaf_count += 1;
+                stats.primitive_count += objects.len();
+                stats.sah_cost += probability * objects.len() as f32 * INTERSECTION_COST;
+            }
+            BuildNode::Interior { left, right, .. } => {
+                stats.sah_cost += probability * TRAVERSAL_COST;
+                left.collect_stats(depth + 1, root_area, stats);
+                right.collect_stats(depth + 1, root_area, stats);
+            }
+        }
+    }
+
+    fn into_hittable(self, objects: &[Arc<dyn Hittable>]) -> Arc<dyn Hittable> {
+        match self {
+            BuildNode::Leaf { objects: leaf, .. } if leaf.len() == 1 => objects[leaf[0]].clone(),
+            BuildNode::Leaf { objects: leaf, .. } => Arc::new(HittableList {
+                items: leaf.iter().map(|&i| objects[i].clone()).collect(),
+            }),
+            BuildNode::Interior {
+                aabb, left, right, ..
+            } => Arc::new(BVHNode {
+                left: left.into_hittable(objects),
+                right: right.into_hittable(objects),
+                aabb,
+            }),
+        }
+    }
+}
+
+struct Builder {
+    boxes: Vec<AABB>,
+    centroids: Vec<Point3>,
+    leaf_size: usize,
+    bins: usize,
+}
+
+#[derive(Copy, Clone)]
+struct Split {
+    cost: f32,
+    axis: usize,
+    /// First bin of the right side.
+    bin: usize,
+    min: f32,
+    extent: f32,
+}
+
+#[derive(Copy, Clone)]
+struct Bin {
+    aabb: Option<AABB>,
+    count: usize,
+}
+
+impl Builder {
+    fn node(&self, objects: Vec<usize>, depth: usize) -> BuildNode {
+        let aabb = self.bounds(&objects);
+
+        if objects.len() == 1 {
+            return BuildNode::Leaf { aabb, objects };
+        }
+
+        let leaf_cost = objects.len() as f32 * INTERSECTION_COST;
+
+        let split = if depth < MAX_SAH_DEPTH {
+            self.best_split(&objects, &aabb)
+        } else {
+            None
+        };
+
+        let (axis, (left, right)) = match split {
+            Some(split) => {
+                if split.cost >= leaf_cost && objects.len() <= self.leaf_size {
+                    return BuildNode::Leaf { aabb, objects };
+                }
+
+                let sides = objects.iter().partition(|&&i| {
+                    self.bin(self.centroids[i][split.axis], split.min, split.extent) < split.bin
+                });
+                (split.axis, sides)
+            }
+            // every centroid is at the same point, no split separates them
+            None if objects.len() <= self.leaf_size => {
+                return BuildNode::Leaf { aabb, objects };
+            }
+            None => {
+                let extent = aabb.maximum - aabb.minimum;
+                let axis = if extent.x > extent.y && extent.x > extent.z {
+                    0
+                } else if extent.y > extent.z {
+                    1
+                } else {
+                    2
+                };
+
+                let mut left = objects;
+                left.sort_by(|&a, &b| {
+                    self.centroids[a][axis]
+                        .partial_cmp(&self.centroids[b][axis])
+                        .unwrap_or(Ordering::Equal)
+                });
+                let right = left.split_off(left.len() / 2);
+                (axis, (left, right))
+            }
+        };
+
+        BuildNode::Interior {
+            aabb,
+            axis,
+            left: Box::new(self.node(left, depth + 1)),
+            right: Box::new(self.node(right, depth + 1)),
+        }
+    }
+
+    /// Finds the cheapest split between bins, if the centroids are spread along
+    /// any axis. Ties go to the lowest axis and bin so builds are reproducible.
+    fn best_split(&self, objects: &[usize], aabb: &AABB) -> Option<Split> {
+        let mut centroid_min = self.centroids[objects[0]];
+        let mut centroid_max = centroid_min;
+        for &i in objects {
+            centroid_min = centroid_min.min(self.centroids[i]);
+            centroid_max = centroid_max.max(self.centroids[i]);
+        }
+
+        let area = aabb.surface_area();
+        let mut best: Option<Split> = None;
 
-    let has_box_a = a.bounding_box(0.0, 0.0, &mut box_a);
-    let has_box_b = b.bounding_box(0.0, 0.0, &mut box_b);
+        for axis in 0..3 {
+            let min = centroid_min[axis];
+            let extent = centroid_max[axis] - min;
+
+            if extent <= 0.0 {
+                continue;
+            }
+
+            let mut bins = vec![
+                Bin {
+                    aabb: None,
+                    count: 0
+                };
+                self.bins
+            ];
+
+            for &i in objects {
+                let bin = &mut bins[self.bin(self.centroids[i][axis], min, extent)];
+                bin.count += 1;
+                bin.aabb = Some(union(bin.aabb, self.boxes[i]));
+            }
 
-    if !has_box_a || !has_box_b {
-        panic!("No bounding box in bvh_node constructor.");
-    };
+            // right to left sweep of the areas and counts past each split
+            let mut right_area = vec![0.0; self.bins];
+            let mut right_count = vec![0; self.bins];
+            let mut accumulated: Option<AABB> = None;
+            let mut count = 0;
+            for b in (1..self.bins).rev() {
+                if let Some(bin_box) = bins[b].aabb {
+                    accumulated = Some(union(accumulated, bin_box));
+                }
+                count += bins[b].count;
+                right_area[b] = accumulated.map_or(0.0, |a| a.surface_area());
+                right_count[b] = count;
+            }
+
+            let mut accumulated: Option<AABB> = None;
+            let mut count = 0;
+            for split in 1..self.bins {
+                if let Some(bin_box) = bins[split - 1].aabb {
+                    accumulated = Some(union(accumulated, bin_box));
+                }
+                count += bins[split - 1].count;
+
+                if count == 0 || right_count[split] == 0 {
+                    continue;
+                }
+
+                let left_area = accumulated.map_or(0.0, |a| a.surface_area());
+                let split_area =
+                    left_area * count as f32 + right_area[split] * right_count[split] as f32;
+                let cost = TRAVERSAL_COST
+                    + if area > 0.0 {
+                        split_area / area * INTERSECTION_COST
+                    } else {
+                        objects.len() as f32 * INTERSECTION_COST
+                    };
+
+                if best.is_none_or(|best| cost < best.cost) {
+                    best = Some(Split {
+                        cost,
+                        axis,
+                        bin: split,
+                        min,
+                        extent,
+                    });
+                }
+            }
+        }
+
+        best
+    }
+
+    fn bin(&self, centroid: f32, min: f32, extent: f32) -> usize {
+        let bin = ((centroid - min) / extent * self.bins as f32) as usize;
+        bin.min(self.bins - 1)
+    }
+
+    fn bounds(&self, objects: &[usize]) -> AABB {
+        objects[1..]
+            .iter()
+            .fold(self.boxes[objects[0]], |aabb, &i| aabb & self.boxes[i])
+    }
+}
 
-    if box_a.minimum[axis] < box_b.minimum[axis] {
-        Ordering::Greater
-    } else {
-        Ordering::Less
+fn union(aabb: Option<AABB>, other: AABB) -> AABB {
+    match aabb {
+        Some(aabb) => aabb & other,
+        None => other,
     }
 }
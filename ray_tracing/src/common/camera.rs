@@ -1,5 +1,6 @@
 use super::helpers::*;
 use super::ray::*;
+use super::sampler::*;
 use super::vec3::*;
 
 const DEG_TO_RAD: f32 = 0.017453292519943295769236907684886;
@@ -12,6 +13,10 @@ pub struct Camera {
     pub lens_radius: f32,
     pub u: Vec3,
     pub v: Vec3,
+    pub aspect: f32,
+    /// Shutter open and close times, rays are spread uniformly in between.
+    pub time0: f32,
+    pub time1: f32,
 }
 
 impl Camera {
@@ -47,18 +52,42 @@ impl Camera {
             u,
             v,
             lens_radius,
+            aspect,
+            time0: 0.0,
+            time1: 0.0,
         }
     }
+
+    /// Widens or narrows the viewport while keeping the vertical field of view.
+    pub fn set_aspect_ratio(&mut self, aspect: f32) {
+        let horizontal = self.horizontal * (aspect / self.aspect);
+
+        self.lower_left_corner = self.lower_left_corner + self.horizontal / 2.0 - horizontal / 2.0;
+        self.horizontal = horizontal;
+        self.aspect = aspect;
+    }
+
+    pub fn set_shutter(&mut self, time0: f32, time1: f32) {
+        self.time0 = time0;
+        self.time1 = time1;
+    }
 }
 
 impl Camera {
-    pub fn get_ray(&self, s: f32, t: f32) -> Ray {
-        let rd = self.lens_radius * random_in_unit_disc();
+    pub fn get_ray(&self, s: f32, t: f32, sampler: &mut dyn Sampler) -> Ray {
+        let rd = self.lens_radius * random_in_unit_disc(sampler);
         let offset = self.u * rd.x + self.v * rd.y;
 
-        Ray::new(
+        let time = if self.time1 > self.time0 {
+            self.time0 + (self.time1 - self.time0) * sampler.get_1d()
+        } else {
+            self.time0
+        };
+
+        Ray::with_time(
             self.origin + offset,
             self.lower_left_corner + s * self.horizontal + t * self.vertical - offset,
+            time,
         )
     }
 }
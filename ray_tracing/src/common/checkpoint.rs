@@ -0,0 +1,288 @@
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use super::framebuffer::*;
+use super::tone_mapping::*;
+use super::vec3::*;
+
+const MAGIC: &[u8; 8] = b"RTCHECK1";
+const HEADER_SIZE: usize = 8 + 3 * 4 + 2 * 8;
+const PIXEL_SIZE: usize = 15 * 4;
+
+#[derive(Debug)]
+pub enum CheckpointError {
+    Io { path: PathBuf, source: io::Error },
+    Invalid { path: PathBuf, message: String },
+}
+
+impl fmt::Display for CheckpointError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CheckpointError::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            CheckpointError::Invalid { path, message } => {
+                write!(f, "{}: {}", path.display(), message)
+            }
+        }
+    }
+}
+
+impl Error for CheckpointError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            CheckpointError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// Samples taken in a pixel, summed up, and the samples of the pixels around it
+/// summed up with the weights of the reconstruction filter.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct PixelSamples {
+    pub sum: Color,
+    /// Sum of the light that bounced once at most, the rest of `sum` is indirect.
+    pub direct: Color,
+    /// Sum of the squared luminance of the samples, for estimating their variance.
+    pub sum_squares: f32,
+    pub count: u32,
+    /// Filter weighted sum of the samples splatted into the pixel.
+    pub weighted: Color,
+    pub weighted_direct: Color,
+    /// Sum of the filter weights.
+    pub weight: f32,
+}
+
+impl PixelSamples {
+    /// Adds a sample of `color` taken in the pixel, `direct` of which bounced once
+    /// at most. It still has to be splatted to show up in the image.
+    pub fn add(&mut self, color: Color, direct: Color) {
+        let l = luminance(color);
+
+        self.sum += color;
+        self.direct += direct;
+        self.sum_squares += l * l;
+        self.count += 1;
+    }
+
+    /// Adds a sample that landed in or near the pixel with the filter `weight`.
+    pub fn splat(&mut self, color: Color, direct: Color, weight: f32) {
+        self.weighted += weight * color;
+        self.weighted_direct += weight * direct;
+        self.weight += weight;
+    }
+
+    /// Adds up the samples of both.
+    pub fn merge(&mut self, other: &PixelSamples) {
+        self.sum += other.sum;
+        self.direct += other.direct;
+        self.sum_squares += other.sum_squares;
+        self.count += other.count;
+        self.weighted += other.weighted;
+        self.weighted_direct += other.weighted_direct;
+        self.weight += other.weight;
+    }
+
+    /// The filtered color of the pixel. The negative lobes of the sharper filters
+    /// can push it below zero next to bright edges, where it is cut off.
+    pub fn mean(&self) -> Color {
+        self.filtered(self.weighted, self.sum)
+    }
+
+    pub fn direct_mean(&self) -> Color {
+        self.filtered(self.weighted_direct, self.direct)
+    }
+
+    pub fn indirect_mean(&self) -> Color {
+        self.filtered(self.weighted - self.weighted_direct, self.sum - self.direct)
+    }
+
+    /// `weighted` divided by the weights, or `sum` by the samples if no weight
+    /// reached the pixel.
+    fn filtered(&self, weighted: Color, sum: Color) -> Color {
+        let color = if self.weight > 0.0 {
+            weighted * (1.0 / self.weight)
+        } else {
+            sum * (1.0 / self.count.max(1) as f32)
+        };
+
+        Color::new(color.x.max(0.0), color.y.max(0.0), color.z.max(0.0))
+    }
+
+    /// Standard error of the mean luminance, how far it is likely off.
+    pub fn standard_error(&self) -> f32 {
+        if self.count < 2 {
+            return f32::INFINITY;
+        }
+
+        let n = self.count as f32;
+        let mean = luminance(self.sum) / n;
+        let variance = (self.sum_squares / n - mean * mean).max(0.0) * n / (n - 1.0);
+
+        (variance / n).sqrt()
+    }
+}
+
+/// Sums of every sample taken so far, enough to carry on a progressive render.
+/// Stored as a header of the magic bytes, width, height, sample count, seed and
+/// settings hash followed by the sum, direct sum, sum of squares, count, weighted
+/// sum, weighted direct sum and weight of every pixel, all little endian.
+#[derive(Debug, Clone)]
+pub struct Checkpoint {
+    pub width: u32,
+    pub height: u32,
+    /// Samples taken in every pixel by the passes so far. Adaptive sampling leaves
+    /// pixels that stopped early with fewer.
+    pub samples: u32,
+    pub seed: u64,
+    /// `settings_hash` of the scene and of the settings that decide what the
+    /// samples are, which a resumed render has to share.
+    pub settings: u64,
+    pub pixels: Vec<PixelSamples>,
+}
+
+impl Checkpoint {
+    pub fn new(width: u32, height: u32, seed: u64, settings: u64) -> Self {
+        Self {
+            width,
+            height,
+            samples: 0,
+            seed,
+            settings,
+            pixels: vec![PixelSamples::default(); (width * height) as usize],
+        }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, CheckpointError> {
+        let path = path.as_ref();
+        let invalid = |message: &str| CheckpointError::Invalid {
+            path: path.to_path_buf(),
+            message: message.to_string(),
+        };
+
+        let bytes = fs::read(path).map_err(|source| CheckpointError::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+
+        if bytes.len() < HEADER_SIZE || &bytes[..8] != MAGIC {
+            return Err(invalid("not a checkpoint file"));
+        }
+
+        let word = |b: &[u8], i: usize| [b[i], b[i + 1], b[i + 2], b[i + 3]];
+        let header = |i: usize| u32::from_le_bytes(word(&bytes, 8 + 4 * i));
+        let (width, height, samples) = (header(0), header(1), header(2));
+        let long = |i: usize| {
+            let mut b = [0; 8];
+            b.copy_from_slice(&bytes[20 + 8 * i..28 + 8 * i]);
+            u64::from_le_bytes(b)
+        };
+        let (seed, settings) = (long(0), long(1));
+
+        let data = &bytes[HEADER_SIZE..];
+        if data.len() != width as usize * height as usize * PIXEL_SIZE {
+            return Err(invalid("truncated checkpoint"));
+        }
+
+        let pixels = data
+            .chunks_exact(PIXEL_SIZE)
+            .map(|c| {
+                let float = |i: usize| f32::from_le_bytes(word(c, i));
+                PixelSamples {
+                    sum: Color::new(float(0), float(4), float(8)),
+                    direct: Color::new(float(12), float(16), float(20)),
+                    sum_squares: float(24),
+                    count: u32::from_le_bytes(word(c, 28)),
+                    weighted: Color::new(float(32), float(36), float(40)),
+                    weighted_direct: Color::new(float(44), float(48), float(52)),
+                    weight: float(56),
+                }
+            })
+            .collect();
+
+        Ok(Self {
+            width,
+            height,
+            samples,
+            seed,
+            settings,
+            pixels,
+        })
+    }
+
+    /// Writes to a temporary file first, so a render killed while saving still
+    /// leaves the previous checkpoint behind.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), CheckpointError> {
+        let path = path.as_ref();
+        let io_error = |source| CheckpointError::Io {
+            path: path.to_path_buf(),
+            source,
+        };
+
+        let mut bytes = Vec::with_capacity(HEADER_SIZE + self.pixels.len() * PIXEL_SIZE);
+        bytes.extend_from_slice(MAGIC);
+        for word in &[self.width, self.height, self.samples] {
+            bytes.extend_from_slice(&word.to_le_bytes());
+        }
+        bytes.extend_from_slice(&self.seed.to_le_bytes());
+        bytes.extend_from_slice(&self.settings.to_le_bytes());
+        for pixel in &self.pixels {
+            let PixelSamples {
+                sum,
+                direct,
+                sum_squares,
+                count,
+                weighted,
+                weighted_direct,
+                weight,
+            } = pixel;
+            for float in &[
+                sum.x,
+                sum.y,
+                sum.z,
+                direct.x,
+                direct.y,
+                direct.z,
+                *sum_squares,
+            ] {
+                bytes.extend_from_slice(&float.to_le_bytes());
+            }
+            bytes.extend_from_slice(&count.to_le_bytes());
+            for float in &[
+                weighted.x,
+                weighted.y,
+                weighted.z,
+                weighted_direct.x,
+                weighted_direct.y,
+                weighted_direct.z,
+                *weight,
+            ] {
+                bytes.extend_from_slice(&float.to_le_bytes());
+            }
+        }
+
+        let mut temporary = path.as_os_str().to_owned();
+        temporary.push(".tmp");
+        fs::write(&temporary, &bytes).map_err(io_error)?;
+        fs::rename(&temporary, path).map_err(io_error)
+    }
+
+    /// Average of the samples taken so far.
+    pub fn framebuffer(&self) -> Framebuffer {
+        Framebuffer::from_pixels(
+            self.width,
+            self.height,
+            self.pixels.iter().map(PixelSamples::mean).collect(),
+        )
+    }
+}
+
+/// FNV-1a hash of `settings`, which unlike the standard library's hasher stays the
+/// same from one build to the next.
+pub fn settings_hash(settings: &[u8]) -> u64 {
+    settings.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
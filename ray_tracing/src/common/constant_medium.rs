@@ -0,0 +1,83 @@
+use std::sync::Arc;
+
+use super::aabb::*;
+use super::hittable::*;
+use super::material::*;
+use super::ray::*;
+use super::sampler::*;
+use super::texture::*;
+use super::vec3::*;
+
+/// Fog or smoke of uniform density filling a closed boundary. Rays scatter at a
+/// random distance inside with the isotropic phase function.
+pub struct ConstantMedium {
+    pub boundary: Arc<dyn Hittable>,
+    pub phase_function: Arc<dyn Material>,
+    pub neg_inv_density: f32,
+}
+
+impl ConstantMedium {
+    pub fn new(boundary: Arc<dyn Hittable>, density: f32, albedo: Arc<dyn Texture>) -> Arc<Self> {
+        Arc::new(Self {
+            boundary,
+            phase_function: Isotropic::new(albedo),
+            neg_inv_density: -1.0 / density,
+        })
+    }
+}
+
+impl Hittable for ConstantMedium {
+    fn hit(
+        &self,
+        ray: &Ray,
+        t_min: f32,
+        t_max: f32,
+        rec: &mut HitRecord,
+        sampler: &mut dyn Sampler,
+    ) -> bool {
+        let mut rec1 = HitRecord::default();
+        let mut rec2 = HitRecord::default();
+
+        if !self
+            .boundary
+            .hit(ray, -f32::INFINITY, f32::INFINITY, &mut rec1, sampler)
+        {
+            return false;
+        }
+
+        if !self
+            .boundary
+            .hit(ray, rec1.t + 0.0001, f32::INFINITY, &mut rec2, sampler)
+        {
+            return false;
+        }
+
+        let t_enter = rec1.t.max(t_min).max(0.0);
+        let t_exit = rec2.t.min(t_max);
+
+        if t_enter >= t_exit {
+            return false;
+        }
+
+        let ray_length = ray.dir.length();
+        let distance_inside_boundary = (t_exit - t_enter) * ray_length;
+        let hit_distance = self.neg_inv_density * sampler.get_1d().ln();
+
+        if hit_distance > distance_inside_boundary {
+            return false;
+        }
+
+        rec.t = t_enter + hit_distance / ray_length;
+        rec.p = ray.at(rec.t);
+        // arbitrary, the phase function doesn't use them
+        rec.normal = Vec3::new(1.0, 0.0, 0.0);
+        rec.front_face = true;
+        rec.material = Some(self.phase_function.clone());
+
+        true
+    }
+
+    fn bounding_box(&self, time0: f32, time1: f32, output_box: &mut AABB) -> bool {
+        self.boundary.bounding_box(time0, time1, output_box)
+    }
+}
@@ -0,0 +1,297 @@
+use rayon::prelude::*;
+use serde::Deserialize;
+
+use super::framebuffer::*;
+use super::tone_mapping::*;
+use super::vec3::*;
+
+/// Weights of the B3 spline the filter spreads over five taps, spaced further apart
+/// by every pass.
+const KERNEL: [f32; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+
+/// Smallest albedo divided out, darker surfaces would blow their noise up.
+const MIN_ALBEDO: f32 = 0.01;
+
+/// Edge avoiding à-trous wavelet filter (Dammertz et al., with the variance guided
+/// luminance weights of SVGF). Every pass blurs with a wider kernel, but only
+/// between pixels with similar normals, depth and brightness, the last judged
+/// against how noisy the pixels are.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct DenoiseSettings {
+    /// Passes of the filter, each one reaching twice as far as the one before.
+    pub iterations: u32,
+    /// Differences in brightness, in standard deviations of the noise, that still
+    /// get blurred together.
+    pub sigma_luminance: f32,
+    /// Exponent of the cosine between two normals, higher keeps creases sharper.
+    pub sigma_normal: f32,
+    /// Depth differences, relative to the slope of the surface, that still get
+    /// blurred together.
+    pub sigma_depth: f32,
+}
+
+impl Default for DenoiseSettings {
+    fn default() -> Self {
+        Self {
+            iterations: 5,
+            sigma_luminance: 4.0,
+            sigma_normal: 128.0,
+            sigma_depth: 1.0,
+        }
+    }
+}
+
+/// What the denoiser keeps the edges of, usually the AOVs of the same render. Any
+/// of them can be left out.
+#[derive(Debug, Clone, Default)]
+pub struct DenoiseGuides {
+    /// Divided out before filtering and multiplied back after, so textures stay sharp.
+    pub albedo: Option<Framebuffer>,
+    pub normal: Option<Framebuffer>,
+    /// Distance from the camera in the first channel, zero where nothing was hit.
+    pub depth: Option<Framebuffer>,
+    /// Variance of the luminance of every pixel, estimated from the image itself
+    /// when missing.
+    pub variance: Option<Vec<f32>>,
+}
+
+/// Pixel features the filter weights are computed from.
+struct Guide {
+    width: i64,
+    height: i64,
+    normal: Option<Vec<Vec3>>,
+    depth: Option<Vec<f32>>,
+    /// Change of depth from one pixel to the next.
+    depth_slope: Vec<f32>,
+}
+
+impl Guide {
+    /// How much the feature edges between pixels `p` and `q`, `distance` pixels
+    /// apart, let them be blurred together.
+    fn weight(&self, settings: &DenoiseSettings, p: usize, q: usize, distance: f32) -> f32 {
+        let mut weight = 1.0;
+
+        if let Some(normal) = &self.normal {
+            let (np, nq) = (normal[p], normal[q]);
+            // pixels where nothing was hit have no normal
+            weight *= match (np.near_zero(), nq.near_zero()) {
+                (true, true) => 1.0,
+                (false, false) => np.dot(nq).max(0.0).powf(settings.sigma_normal),
+                _ => 0.0,
+            };
+        }
+
+        if let Some(depth) = &self.depth {
+            let (zp, zq) = (depth[p], depth[q]);
+            weight *= match (zp > 0.0, zq > 0.0) {
+                (false, false) => 1.0,
+                (true, true) => {
+                    let expected = settings.sigma_depth * self.depth_slope[p] * distance;
+                    (-(zp - zq).abs() / (expected + 1.0e-3 * zp)).exp()
+                }
+                _ => 0.0,
+            };
+        }
+
+        weight
+    }
+
+    fn neighbours(&self, p: usize, step: i64) -> impl Iterator<Item = (usize, f32, f32)> + '_ {
+        let (x, y) = (p as i64 % self.width, p as i64 / self.width);
+
+        (-2..=2_i64).flat_map(move |dy| {
+            (-2..=2_i64).filter_map(move |dx| {
+                let (qx, qy) = (x + dx * step, y + dy * step);
+                if qx < 0 || qx >= self.width || qy < 0 || qy >= self.height {
+                    return None;
+                }
+
+                let kernel = KERNEL[(dx + 2) as usize] * KERNEL[(dy + 2) as usize];
+                let distance = ((dx * dx + dy * dy) as f32).sqrt() * step as f32;
+
+                Some(((qy * self.width + qx) as usize, kernel, distance))
+            })
+        })
+    }
+}
+
+/// Filters the noise out of a linear image.
+pub fn denoise(
+    color: &Framebuffer,
+    guides: &DenoiseGuides,
+    settings: &DenoiseSettings,
+) -> Framebuffer {
+    let (width, height) = (color.width as i64, color.height as i64);
+    let at = |x: i64, y: i64| (y.clamp(0, height - 1) * width + x.clamp(0, width - 1)) as usize;
+
+    let albedo: Vec<Color> = match &guides.albedo {
+        Some(albedo) => albedo
+            .pixels
+            .iter()
+            .map(|a| {
+                Color::new(
+                    a.x.max(MIN_ALBEDO),
+                    a.y.max(MIN_ALBEDO),
+                    a.z.max(MIN_ALBEDO),
+                )
+            })
+            .collect(),
+        None => vec![Color::new(1.0, 1.0, 1.0); color.pixels.len()],
+    };
+
+    let normal = guides.normal.as_ref().map(|normal| {
+        normal
+            .pixels
+            .iter()
+            .map(|n| if n.near_zero() { *n } else { n.unit_vector() })
+            .collect()
+    });
+    let depth: Option<Vec<f32>> = guides
+        .depth
+        .as_ref()
+        .map(|depth| depth.pixels.iter().map(|d| d.x).collect());
+    let depth_slope = match &depth {
+        Some(depth) => (0..color.pixels.len() as i64)
+            .map(|p| {
+                let (x, y) = (p % width, p / width);
+                let slope = |a: f32, b: f32| {
+                    if a > 0.0 && b > 0.0 {
+                        (a - b).abs() / 2.0
+                    } else {
+                        0.0
+                    }
+                };
+
+                slope(depth[at(x - 1, y)], depth[at(x + 1, y)])
+                    .max(slope(depth[at(x, y - 1)], depth[at(x, y + 1)]))
+            })
+            .collect(),
+        None => vec![],
+    };
+
+    let guide = Guide {
+        width,
+        height,
+        normal,
+        depth,
+        depth_slope,
+    };
+
+    // lighting without the texture of the surfaces
+    let mut irradiance: Vec<Color> = color
+        .pixels
+        .iter()
+        .zip(&albedo)
+        .map(|(c, a)| *c / *a)
+        .collect();
+
+    let mut variance: Vec<f32> = match &guides.variance {
+        Some(variance) => variance
+            .iter()
+            .zip(&albedo)
+            .map(|(v, a)| v / (luminance(*a) * luminance(*a)))
+            .collect(),
+        None => estimate_variance(&irradiance, &guide, settings),
+    };
+
+    for i in 0..settings.iterations {
+        let step = 1_i64 << i;
+        // a pass reaching past the image only weighs the pixel itself, changing nothing
+        if step >= width.max(height) {
+            break;
+        }
+        // single pixels shouldn't decide what is noise
+        let smoothed: Vec<f32> = (0..variance.len())
+            .map(|p| {
+                let (x, y) = (p as i64 % width, p as i64 / width);
+                let mut sum = 0.0;
+                for dy in -1..=1 {
+                    for dx in -1..=1 {
+                        let kernel = [0.25, 0.5, 0.25];
+                        sum += kernel[(dx + 1) as usize]
+                            * kernel[(dy + 1) as usize]
+                            * variance[at(x + dx, y + dy)];
+                    }
+                }
+                sum
+            })
+            .collect();
+
+        let (next_irradiance, next_variance) = (0..irradiance.len())
+            .into_par_iter()
+            .map(|p| {
+                let lp = luminance(irradiance[p]);
+                let sigma = settings.sigma_luminance * smoothed[p].max(0.0).sqrt() + 1.0e-6;
+
+                let mut weights = 0.0;
+                let mut sum = Color::zero();
+                let mut sum_variance = 0.0;
+
+                for (q, kernel, distance) in guide.neighbours(p, step) {
+                    let similarity = (-(lp - luminance(irradiance[q])).abs() / sigma).exp();
+                    let weight = kernel * similarity * guide.weight(settings, p, q, distance);
+
+                    weights += weight;
+                    sum += weight * irradiance[q];
+                    sum_variance += weight * weight * variance[q];
+                }
+
+                // the pixel itself always weighs in, so the sum is never zero
+                (sum / weights, sum_variance / (weights * weights))
+            })
+            .unzip();
+
+        irradiance = next_irradiance;
+        variance = next_variance;
+    }
+
+    let pixels = irradiance
+        .iter()
+        .zip(&albedo)
+        .map(|(irradiance, albedo)| *irradiance * *albedo)
+        .collect();
+
+    Framebuffer::from_pixels(color.width, color.height, pixels)
+}
+
+/// Variance of the luminance of every pixel, from how far it is off the pixels next
+/// to it on the same surface. That alone is too noisy and rises at every edge the
+/// guides miss, so the noise relative to the brightness is taken over the whole
+/// image instead, and scaled back by the brightness of every pixel.
+fn estimate_variance(irradiance: &[Color], guide: &Guide, settings: &DenoiseSettings) -> Vec<f32> {
+    let mut relative: Vec<f32> = (0..irradiance.len())
+        .into_par_iter()
+        .filter_map(|p| {
+            let mut weights = 0.0;
+            let mut sum = 0.0;
+
+            for (q, kernel, distance) in guide.neighbours(p, 1).filter(|&(q, _, _)| q != p) {
+                let weight = kernel * guide.weight(settings, p, q, distance);
+
+                weights += weight;
+                sum += weight * luminance(irradiance[q]);
+            }
+
+            if weights <= 0.0 || sum <= 0.0 {
+                return None;
+            }
+
+            let deviation = luminance(irradiance[p]) * weights / sum - 1.0;
+            Some(deviation * deviation)
+        })
+        .collect();
+
+    if relative.is_empty() {
+        return vec![0.0; irradiance.len()];
+    }
+
+    // the median ignores the edges and fireflies
+    let middle = relative.len() / 2;
+    let (_, &mut noise, _) = relative.select_nth_unstable_by(middle, |a, b| a.total_cmp(b));
+
+    irradiance
+        .iter()
+        .map(|c| noise * luminance(*c) * luminance(*c))
+        .collect()
+}
@@ -0,0 +1,139 @@
+use std::f32::consts::PI;
+
+use serde::Deserialize;
+
+/// Curve weighting the samples around a pixel by how far they land from its center.
+#[derive(Debug, Copy, Clone, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FilterKind {
+    /// Every sample counts fully in the pixel it lands in and nowhere else.
+    #[default]
+    Box,
+    /// Weights falling in a straight line to zero at the radius.
+    Tent,
+    /// Bell curve cut at three standard deviations, soft but never ringing.
+    Gaussian,
+    /// Mitchell-Netravali cubic with B = C = 1/3, sharper than the Gaussian with a
+    /// slight negative lobe.
+    Mitchell,
+    /// Sinc windowed by a wider sinc, the sharpest, at the cost of ringing around
+    /// hard edges.
+    Lanczos,
+}
+
+impl FilterKind {
+    /// Radius in pixels the filter is usually used with.
+    pub fn default_radius(&self) -> f32 {
+        match self {
+            FilterKind::Box => 0.5,
+            FilterKind::Tent => 1.0,
+            FilterKind::Gaussian => 1.5,
+            FilterKind::Mitchell => 2.0,
+            FilterKind::Lanczos => 3.0,
+        }
+    }
+}
+
+/// Reconstruction filter of the image: every sample is splatted into the pixels
+/// whose centers are within `radius` of it, weighted by the filter, and every pixel
+/// is the weighted average of the samples it got.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Filter {
+    pub kind: FilterKind,
+    /// In pixels, on both axes.
+    pub radius: f32,
+}
+
+impl Filter {
+    /// `kind` with `radius`, or with its usual radius when none is given.
+    pub fn new(kind: FilterKind, radius: Option<f32>) -> Self {
+        Self {
+            kind,
+            radius: radius.unwrap_or_else(|| kind.default_radius()),
+        }
+    }
+
+    /// Weight of a sample `dx` and `dy` pixels away from the center of a pixel,
+    /// negative in the lobes of the sharper filters.
+    pub fn weight(&self, dx: f32, dy: f32) -> f32 {
+        self.weight_1d(dx) * self.weight_1d(dy)
+    }
+
+    fn weight_1d(&self, d: f32) -> f32 {
+        let (d, radius) = (d.abs(), self.radius);
+        if d > radius {
+            return 0.0;
+        }
+
+        match self.kind {
+            FilterKind::Box => 1.0,
+            FilterKind::Tent => 1.0 - d / radius,
+            FilterKind::Gaussian => {
+                // shifted down to reach zero at the radius instead of stopping short
+                let sigma = radius / 3.0;
+                let gaussian = |x: f32| (-x * x / (2.0 * sigma * sigma)).exp();
+                gaussian(d) - gaussian(radius)
+            }
+            FilterKind::Mitchell => mitchell(2.0 * d / radius),
+            FilterKind::Lanczos => sinc(d) * sinc(d / radius),
+        }
+    }
+
+    /// Pixels past its own a sample can land in, on every side.
+    pub fn reach(&self) -> u32 {
+        (self.radius - 0.5).ceil().max(0.0) as u32
+    }
+
+    /// Pixels of a row or column `size` pixels long that a sample `offset` into
+    /// pixel `pixel` counts in, with their centers' offsets from the sample. A
+    /// sample on the edge between two pixels only goes to the second with the box
+    /// filter, like it would without a filter.
+    pub fn footprint(
+        &self,
+        pixel: u32,
+        offset: f32,
+        size: u32,
+    ) -> impl Iterator<Item = (u32, f32)> {
+        let pixel = pixel as i64;
+        let first = (offset - 0.5 - self.radius).floor() as i64 + 1;
+        let last = (offset - 0.5 + self.radius).floor() as i64;
+
+        (first.max(-pixel)..=last.min(size as i64 - 1 - pixel))
+            .map(move |d| ((pixel + d) as u32, d as f32 + 0.5 - offset))
+    }
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Self::new(FilterKind::default(), None)
+    }
+}
+
+/// Mitchell-Netravali cubic over `[0, 2]` with B = C = 1/3.
+fn mitchell(x: f32) -> f32 {
+    const B: f32 = 1.0 / 3.0;
+    const C: f32 = 1.0 / 3.0;
+
+    let value = if x < 1.0 {
+        (12.0 - 9.0 * B - 6.0 * C) * x * x * x
+            + (-18.0 + 12.0 * B + 6.0 * C) * x * x
+            + (6.0 - 2.0 * B)
+    } else if x < 2.0 {
+        (-B - 6.0 * C) * x * x * x
+            + (6.0 * B + 30.0 * C) * x * x
+            + (-12.0 * B - 48.0 * C) * x
+            + (8.0 * B + 24.0 * C)
+    } else {
+        0.0
+    };
+
+    value / 6.0
+}
+
+fn sinc(x: f32) -> f32 {
+    if x.abs() < 1.0e-5 {
+        return 1.0;
+    }
+
+    (PI * x).sin() / (PI * x)
+}
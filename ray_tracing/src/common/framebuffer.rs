@@ -0,0 +1,272 @@
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+
+use image::codecs::hdr::{HdrDecoder, HdrEncoder};
+use image::{ImageBuffer, Rgb};
+use serde::Deserialize;
+
+use super::tone_mapping::*;
+use super::vec3::*;
+
+/// File format of a saved image. Only `exr` and `hdr` keep the linear radiance, the
+/// others go through the `DisplaySettings`.
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputFormat {
+    /// 8 bits per channel in whatever format the extension names, like bmp or png.
+    Ldr,
+    /// 16 bits per channel png.
+    Png16,
+    /// OpenEXR with 32 bit float channels.
+    Exr,
+    /// Radiance RGBE.
+    Hdr,
+}
+
+impl OutputFormat {
+    /// Guesses the format from the extension, 16 bit png has to be asked for.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Self {
+        let extension = path
+            .as_ref()
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_ascii_lowercase());
+
+        match extension.as_deref() {
+            Some("exr") => OutputFormat::Exr,
+            Some("hdr") => OutputFormat::Hdr,
+            _ => OutputFormat::Ldr,
+        }
+    }
+}
+
+/// Failure to write an image, or to read one back.
+#[derive(Debug)]
+pub enum OutputError {
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    Image {
+        path: PathBuf,
+        source: image::ImageError,
+    },
+    Exr {
+        path: PathBuf,
+        source: exr::error::Error,
+    },
+}
+
+impl fmt::Display for OutputError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OutputError::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            OutputError::Image { path, source } => write!(f, "{}: {}", path.display(), source),
+            OutputError::Exr { path, source } => write!(f, "{}: {}", path.display(), source),
+        }
+    }
+}
+
+impl Error for OutputError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            OutputError::Io { source, .. } => Some(source),
+            OutputError::Image { source, .. } => Some(source),
+            OutputError::Exr { source, .. } => Some(source),
+        }
+    }
+}
+
+/// Linear radiance of every pixel, averaged over its samples, rows from the top.
+#[derive(Debug, Clone)]
+pub struct Framebuffer {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<Color>,
+}
+
+impl Framebuffer {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![Color::zero(); (width * height) as usize],
+        }
+    }
+
+    /// Panics if `pixels` doesn't hold `width * height` colors.
+    pub fn from_pixels(width: u32, height: u32, pixels: Vec<Color>) -> Self {
+        assert_eq!(
+            pixels.len(),
+            (width * height) as usize,
+            "framebuffer size doesn't match its pixels"
+        );
+
+        Self {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    /// Reads an image saved in any of the `OutputFormat`s. 8 and 16 bit images are
+    /// decoded from sRGB, their tone mapping can't be undone.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, OutputError> {
+        let path = path.as_ref();
+        let image_error = |source| OutputError::Image {
+            path: path.to_path_buf(),
+            source,
+        };
+
+        match OutputFormat::from_path(path) {
+            OutputFormat::Exr => {
+                let image = exr::prelude::read_first_rgba_layer_from_file(
+                    path,
+                    |resolution, _| {
+                        Framebuffer::new(resolution.width() as u32, resolution.height() as u32)
+                    },
+                    |framebuffer: &mut Framebuffer,
+                     position,
+                     (r, g, b, _): (f32, f32, f32, f32)| {
+                        framebuffer.set(
+                            position.x() as u32,
+                            position.y() as u32,
+                            Color::new(r, g, b),
+                        )
+                    },
+                )
+                .map_err(|source| OutputError::Exr {
+                    path: path.to_path_buf(),
+                    source,
+                })?;
+
+                Ok(image.layer_data.channel_data.pixels)
+            }
+            OutputFormat::Hdr => {
+                let file = File::open(path).map_err(|source| OutputError::Io {
+                    path: path.to_path_buf(),
+                    source,
+                })?;
+                let decoder = HdrDecoder::new(BufReader::new(file)).map_err(image_error)?;
+                let metadata = decoder.metadata();
+                let pixels = decoder
+                    .read_image_hdr()
+                    .map_err(image_error)?
+                    .iter()
+                    .map(|Rgb([r, g, b])| Color::new(*r, *g, *b))
+                    .collect();
+
+                Ok(Self::from_pixels(metadata.width, metadata.height, pixels))
+            }
+            OutputFormat::Ldr | OutputFormat::Png16 => {
+                let image = image::open(path).map_err(image_error)?.to_rgb16();
+                let pixels = image
+                    .pixels()
+                    .map(|Rgb([r, g, b])| {
+                        let channel = |v: u16| srgb_to_linear(v as f32 / 65535.0);
+                        Color::new(channel(*r), channel(*g), channel(*b))
+                    })
+                    .collect();
+
+                Ok(Self::from_pixels(image.width(), image.height(), pixels))
+            }
+        }
+    }
+
+    pub fn get(&self, x: u32, y: u32) -> Color {
+        self.pixels[(y * self.width + x) as usize]
+    }
+
+    pub fn set(&mut self, x: u32, y: u32, color: Color) {
+        self.pixels[(y * self.width + x) as usize] = color;
+    }
+
+    pub fn to_rgb8(&self, display: &DisplaySettings) -> ImageBuffer<Rgb<u8>, Vec<u8>> {
+        let pixels = self
+            .pixels
+            .iter()
+            .flat_map(|c| {
+                let c = display.encode(c);
+                vec![c.x, c.y, c.z]
+            })
+            .map(|v| (255.0 * v).round() as u8)
+            .collect();
+
+        ImageBuffer::from_vec(self.width, self.height, pixels).unwrap()
+    }
+
+    pub fn to_rgb16(&self, display: &DisplaySettings) -> ImageBuffer<Rgb<u16>, Vec<u16>> {
+        let pixels = self
+            .pixels
+            .iter()
+            .flat_map(|c| {
+                let c = display.encode(c);
+                vec![c.x, c.y, c.z]
+            })
+            .map(|v| (65535.0 * v).round() as u16)
+            .collect();
+
+        ImageBuffer::from_vec(self.width, self.height, pixels).unwrap()
+    }
+
+    /// `display` only applies to the 8 and 16 bit formats.
+    pub fn save<P: AsRef<Path>>(
+        &self,
+        path: P,
+        format: OutputFormat,
+        display: &DisplaySettings,
+    ) -> Result<(), OutputError> {
+        let path = path.as_ref();
+        let image_error = |source| OutputError::Image {
+            path: path.to_path_buf(),
+            source,
+        };
+
+        match format {
+            OutputFormat::Ldr => self.to_rgb8(display).save(path).map_err(image_error),
+            OutputFormat::Png16 => self
+                .to_rgb16(display)
+                .save_with_format(path, image::ImageFormat::Png)
+                .map_err(image_error),
+            OutputFormat::Exr => exr::prelude::write_rgb_file(
+                path,
+                self.width as usize,
+                self.height as usize,
+                |x, y| {
+                    let c = self.pixels[y * self.width as usize + x];
+                    (c.x, c.y, c.z)
+                },
+            )
+            .map_err(|source| OutputError::Exr {
+                path: path.to_path_buf(),
+                source,
+            }),
+            OutputFormat::Hdr => {
+                let file = File::create(path).map_err(|source| OutputError::Io {
+                    path: path.to_path_buf(),
+                    source,
+                })?;
+                let pixels: Vec<Rgb<f32>> =
+                    self.pixels.iter().map(|c| Rgb([c.x, c.y, c.z])).collect();
+
+                HdrEncoder::new(BufWriter::new(file))
+                    .encode(&pixels, self.width as usize, self.height as usize)
+                    .map_err(image_error)
+            }
+        }
+    }
+}
+
+/// Path of an image saved along with the one at `path`, `cornell.png` gets its
+/// albedo in `cornell.albedo.png`.
+pub fn companion_path(path: &str, name: &str) -> String {
+    let path = Path::new(path);
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("png");
+
+    path.with_extension(format!("{}.{}", name, extension))
+        .to_string_lossy()
+        .into_owned()
+}
@@ -0,0 +1,418 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use gltf::image::Format;
+use gltf::material::AlphaMode;
+use gltf::mesh::Mode;
+use gltf::texture::MagFilter;
+use image::{ImageBuffer, Rgb};
+use nalgebra as na;
+
+use super::attribute::*;
+use super::camera::*;
+use super::hittable::*;
+use super::linear_bvh::*;
+use super::material::*;
+use super::texture::*;
+use super::triangle::*;
+use super::vec2::*;
+use super::vec3::*;
+
+#[derive(Debug)]
+pub enum GltfError {
+    Import {
+        path: PathBuf,
+        source: gltf::Error,
+    },
+    UnsupportedImage {
+        path: PathBuf,
+        index: usize,
+        format: Format,
+    },
+    Empty {
+        path: PathBuf,
+    },
+    /// A primitive whose attributes or indices don't fit together.
+    Invalid {
+        path: PathBuf,
+        mesh: String,
+        primitive: usize,
+        message: String,
+    },
+}
+
+impl fmt::Display for GltfError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GltfError::Import { path, source } => write!(f, "{}: {}", path.display(), source),
+            GltfError::UnsupportedImage {
+                path,
+                index,
+                format,
+            } => write!(
+                f,
+                "{}: image {} has unsupported format {:?}",
+                path.display(),
+                index,
+                format
+            ),
+            GltfError::Empty { path } => write!(f, "{}: no triangles", path.display()),
+            GltfError::Invalid {
+                path,
+                mesh,
+                primitive,
+                message,
+            } => write!(
+                f,
+                "{}: mesh {} primitive {}: {}",
+                path.display(),
+                mesh,
+                primitive,
+                message
+            ),
+        }
+    }
+}
+
+impl Error for GltfError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            GltfError::Import { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// A perspective camera found in the node hierarchy, in world space.
+#[derive(Debug, Copy, Clone)]
+pub struct GltfCamera {
+    pub look_from: Point3,
+    pub look_at: Point3,
+    pub v_up: Vec3,
+    /// Vertical field of view in degrees.
+    pub vfov: f32,
+    pub aspect_ratio: Option<f32>,
+}
+
+impl GltfCamera {
+    pub fn camera(&self, aspect_ratio: f32) -> Camera {
+        Camera::new(
+            self.look_from,
+            self.look_at,
+            self.v_up,
+            self.vfov,
+            aspect_ratio,
+            0.0,
+            10.0,
+        )
+    }
+}
+
+pub struct GltfScene {
+    pub world: Arc<dyn Hittable>,
+    pub cameras: Vec<GltfCamera>,
+}
+
+/// Imports the default scene of a glTF or GLB file. Node transforms are baked into
+/// the triangles, only triangle primitives and perspective cameras are used.
+/// `material` replaces every material of the file when set.
+pub fn load_gltf<P: AsRef<Path>>(
+    path: P,
+    material: Option<Arc<dyn Material>>,
+) -> Result<GltfScene, GltfError> {
+    let path = path.as_ref();
+    let (document, buffers, images) = gltf::import(path).map_err(|source| GltfError::Import {
+        path: path.to_path_buf(),
+        source,
+    })?;
+
+    let mut importer = Importer {
+        path,
+        buffers: &buffers,
+        images: &images,
+        material,
+        materials: HashMap::new(),
+        textures: HashMap::new(),
+        triangles: vec![],
+        cameras: vec![],
+    };
+
+    let scene = document
+        .default_scene()
+        .or_else(|| document.scenes().next());
+
+    if let Some(scene) = scene {
+        for node in scene.nodes() {
+            importer.node(&node, na::Matrix4::identity())?;
+        }
+    }
+
+    if importer.triangles.is_empty() {
+        return Err(GltfError::Empty {
+            path: path.to_path_buf(),
+        });
+    }
+
+    Ok(GltfScene {
+        world: Arc::new(LinearBVH::new(&importer.triangles, 0.0, f32::MAX)),
+        cameras: importer.cameras,
+    })
+}
+
+struct Importer<'a> {
+    path: &'a Path,
+    buffers: &'a [gltf::buffer::Data],
+    images: &'a [gltf::image::Data],
+    material: Option<Arc<dyn Material>>,
+    materials: HashMap<Option<usize>, Arc<dyn Material>>,
+    textures: HashMap<usize, Arc<dyn Texture>>,
+    triangles: Vec<Arc<dyn Hittable>>,
+    cameras: Vec<GltfCamera>,
+}
+
+impl<'a> Importer<'a> {
+    fn node(&mut self, node: &gltf::Node, parent: na::Matrix4<f32>) -> Result<(), GltfError> {
+        let transform = parent * na::Matrix4::from(node.transform().matrix());
+
+        if let Some(mesh) = node.mesh() {
+            for primitive in mesh.primitives() {
+                self.primitive(&mesh, &primitive, &transform)?;
+            }
+        }
+
+        if let Some(camera) = node.camera() {
+            if let gltf::camera::Projection::Perspective(perspective) = camera.projection() {
+                let look_from = transform.transform_point(&na::Point3::origin());
+                let forward = transform.transform_vector(&-na::Vector3::z());
+                let up = transform.transform_vector(&na::Vector3::y());
+
+                self.cameras.push(GltfCamera {
+                    look_from: to_vec3(&look_from.coords),
+                    look_at: to_vec3(&(look_from.coords + forward)),
+                    v_up: to_vec3(&up),
+                    vfov: perspective.yfov().to_degrees(),
+                    aspect_ratio: perspective.aspect_ratio(),
+                });
+            }
+        }
+
+        for child in node.children() {
+            self.node(&child, transform)?;
+        }
+
+        Ok(())
+    }
+
+    fn primitive(
+        &mut self,
+        mesh: &gltf::Mesh,
+        primitive: &gltf::Primitive,
+        transform: &na::Matrix4<f32>,
+    ) -> Result<(), GltfError> {
+        if primitive.mode() != Mode::Triangles {
+            return Ok(());
+        }
+
+        let reader = primitive.reader(|buffer| Some(&self.buffers[buffer.index()]));
+        let invalid = |message: String| GltfError::Invalid {
+            path: self.path.to_path_buf(),
+            mesh: match mesh.name() {
+                Some(name) => format!("`{}`", name),
+                None => mesh.index().to_string(),
+            },
+            primitive: primitive.index(),
+            message,
+        };
+
+        let positions: Vec<Vec3> = match reader.read_positions() {
+            Some(positions) => positions
+                .map(|p| {
+                    let p = transform.transform_point(&na::Point3::new(p[0], p[1], p[2]));
+                    to_vec3(&p.coords)
+                })
+                .collect(),
+            None => return Ok(()),
+        };
+
+        // only the linear part applies to normals, the translation would end up
+        // in the homogeneous row of the inverse transpose
+        let normal_transform = transform
+            .try_inverse()
+            .unwrap_or_else(na::Matrix4::identity)
+            .fixed_slice::<3, 3>(0, 0)
+            .transpose();
+        let normals: Option<Vec<Vec3>> = reader.read_normals().map(|normals| {
+            normals
+                .map(|n| {
+                    let n = normal_transform * na::Vector3::new(n[0], n[1], n[2]);
+                    to_vec3(&n).unit_vector()
+                })
+                .collect()
+        });
+        let uvs: Option<Vec<Vec2>> = reader
+            .read_tex_coords(0)
+            .map(|uvs| uvs.into_f32().map(|t| Vec2::new(t[0], t[1])).collect());
+        let indices: Vec<u32> = match reader.read_indices() {
+            Some(indices) => indices.into_u32().collect(),
+            None => (0..positions.len() as u32).collect(),
+        };
+
+        if let Some(normals) = &normals {
+            if normals.len() != positions.len() {
+                return Err(invalid(format!(
+                    "{} normals for {} positions",
+                    normals.len(),
+                    positions.len()
+                )));
+            }
+        }
+        if let Some(uvs) = &uvs {
+            if uvs.len() != positions.len() {
+                return Err(invalid(format!(
+                    "{} texture coordinates for {} positions",
+                    uvs.len(),
+                    positions.len()
+                )));
+            }
+        }
+        if let Some((i, index)) = indices
+            .iter()
+            .enumerate()
+            .find(|(_, &index)| index as usize >= positions.len())
+        {
+            return Err(invalid(format!(
+                "index[{}] is {}, but there are only {} vertices",
+                i,
+                index,
+                positions.len()
+            )));
+        }
+
+        // mirroring transforms flip the winding, swap it back so faces keep facing out
+        let mirrored = transform.fixed_slice::<3, 3>(0, 0).determinant() < 0.0;
+        let material = self.material(&primitive.material())?;
+
+        for face in indices.chunks_exact(3) {
+            let (ai, mut bi, mut ci) = (face[0] as usize, face[1] as usize, face[2] as usize);
+            if mirrored {
+                std::mem::swap(&mut bi, &mut ci);
+            }
+
+            let position = Attribute::new(positions[ai], positions[bi], positions[ci]);
+            let face_normal = (position.b - position.a).cross(position.c - position.a);
+
+            // degenerate faces have no normal and can't be hit anyway
+            if face_normal.length_squared() == 0.0 {
+                continue;
+            }
+
+            let normal = match &normals {
+                Some(n) => Attribute::new(n[ai], n[bi], n[ci]),
+                None => {
+                    let n = face_normal.unit_vector();
+                    Attribute::new(n, n, n)
+                }
+            };
+            let uv = match &uvs {
+                Some(t) => Attribute::new(t[ai], t[bi], t[ci]),
+                None => Attribute::default(),
+            };
+
+            self.triangles
+                .push(Triangle::new(position, normal, uv, material.clone()));
+        }
+
+        Ok(())
+    }
+
+    /// Maps a metallic-roughness material: emissive materials become lights,
+    /// blended translucent ones glass, metallic ones metal and the rest diffuse.
+    fn material(&mut self, material: &gltf::Material) -> Result<Arc<dyn Material>, GltfError> {
+        if let Some(material) = &self.material {
+            return Ok(material.clone());
+        }
+
+        if let Some(cached) = self.materials.get(&material.index()) {
+            return Ok(cached.clone());
+        }
+
+        let pbr = material.pbr_metallic_roughness();
+        let [r, g, b, a] = pbr.base_color_factor();
+        let base_color = Color::new(r, g, b);
+        let emissive = Vec3::new(
+            material.emissive_factor()[0],
+            material.emissive_factor()[1],
+            material.emissive_factor()[2],
+        );
+
+        let result: Arc<dyn Material> = if !emissive.near_zero() {
+            DiffuseLight::new(emissive).arc()
+        } else if material.alpha_mode() == AlphaMode::Blend && a < 1.0 {
+            Dielectric::new(1.5)
+        } else if pbr.metallic_factor() >= 0.5 {
+            Metal::new(base_color, pbr.roughness_factor())
+        } else {
+            let albedo = match pbr.base_color_texture() {
+                Some(info) => self.texture(&info.texture())?,
+                None => SolidColor::new(base_color),
+            };
+            Lambertian::new(albedo)
+        };
+
+        self.materials.insert(material.index(), result.clone());
+
+        Ok(result)
+    }
+
+    fn texture(&mut self, texture: &gltf::Texture) -> Result<Arc<dyn Texture>, GltfError> {
+        if let Some(cached) = self.textures.get(&texture.index()) {
+            return Ok(cached.clone());
+        }
+
+        let index = texture.source().index();
+        let data = &self.images[index];
+        let channels = match data.format {
+            Format::R8G8B8 => 3,
+            Format::R8G8B8A8 => 4,
+            format => {
+                return Err(GltfError::UnsupportedImage {
+                    path: self.path.to_path_buf(),
+                    index,
+                    format,
+                })
+            }
+        };
+
+        let pixels = data
+            .pixels
+            .chunks_exact(channels)
+            .flat_map(|p| p[..3].iter().copied())
+            .collect();
+        let image = ImageBuffer::<Rgb<u8>, Vec<u8>>::from_vec(data.width, data.height, pixels)
+            .ok_or_else(|| GltfError::UnsupportedImage {
+                path: self.path.to_path_buf(),
+                index,
+                format: data.format,
+            })?;
+
+        let filtering = match texture.sampler().mag_filter() {
+            Some(MagFilter::Nearest) => TextureFiltering::Nearest,
+            _ => TextureFiltering::Linear,
+        };
+
+        // glTF puts the uv origin at the top left corner, like the image rows
+        let result: Arc<dyn Texture> =
+            ImageTexture::from_image(image, filtering, TextureFlip::AsIs, Vec2::new(1.0, 1.0));
+
+        self.textures.insert(texture.index(), result.clone());
+
+        Ok(result)
+    }
+}
+
+fn to_vec3(v: &na::Vector3<f32>) -> Vec3 {
+    Vec3::new(v.x, v.y, v.z)
+}
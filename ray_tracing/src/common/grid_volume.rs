@@ -0,0 +1,304 @@
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use super::aabb::*;
+use super::hittable::*;
+use super::material::*;
+use super::ray::*;
+use super::sampler::*;
+use super::texture::*;
+use super::vec3::*;
+
+#[derive(Debug)]
+pub enum GridError {
+    Io {
+        path: PathBuf,
+        source: io::Error,
+    },
+    Size {
+        path: PathBuf,
+        expected: usize,
+        found: usize,
+    },
+    /// The boundary can't hold a grid.
+    Boundary {
+        message: String,
+    },
+}
+
+impl fmt::Display for GridError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GridError::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            GridError::Size {
+                path,
+                expected,
+                found,
+            } => write!(
+                f,
+                "{}: expected {} bytes for the grid dimensions, found {}",
+                path.display(),
+                expected,
+                found
+            ),
+            GridError::Boundary { message } => write!(f, "{}", message),
+        }
+    }
+}
+
+impl Error for GridError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            GridError::Io { source, .. } => Some(source),
+            GridError::Size { .. } | GridError::Boundary { .. } => None,
+        }
+    }
+}
+
+/// Densities sampled on a regular grid of cells, x varying fastest.
+pub struct DensityGrid {
+    pub dims: [usize; 3],
+    pub data: Vec<f32>,
+    pub max_density: f32,
+}
+
+impl DensityGrid {
+    pub fn new(dims: [usize; 3], data: Vec<f32>) -> Self {
+        assert_eq!(dims[0] * dims[1] * dims[2], data.len());
+
+        let max_density = data.iter().fold(0.0, |max: f32, d| max.max(*d));
+
+        Self {
+            dims,
+            data,
+            max_density,
+        }
+    }
+
+    /// Evaluates `density` at the center of every cell, in coordinates from 0 to 1.
+    pub fn from_fn<F: Fn(Point3) -> f32>(dims: [usize; 3], density: F) -> Self {
+        let mut data = Vec::with_capacity(dims[0] * dims[1] * dims[2]);
+
+        for z in 0..dims[2] {
+            for y in 0..dims[1] {
+                for x in 0..dims[0] {
+                    let p = Point3::new(
+                        (x as f32 + 0.5) / dims[0] as f32,
+                        (y as f32 + 0.5) / dims[1] as f32,
+                        (z as f32 + 0.5) / dims[2] as f32,
+                    );
+                    data.push(density(p).max(0.0));
+                }
+            }
+        }
+
+        Self::new(dims, data)
+    }
+
+    /// Reads little endian 32 bit floats without any header, as dumped by most
+    /// simulation tools.
+    pub fn load_raw<P: AsRef<Path>>(path: P, dims: [usize; 3]) -> Result<Self, GridError> {
+        let path = path.as_ref();
+        let bytes = fs::read(path).map_err(|source| GridError::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+
+        let expected = dims[0] * dims[1] * dims[2] * 4;
+        if bytes.len() != expected {
+            return Err(GridError::Size {
+                path: path.to_path_buf(),
+                expected,
+                found: bytes.len(),
+            });
+        }
+
+        let data = bytes
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]).max(0.0))
+            .collect();
+
+        Ok(Self::new(dims, data))
+    }
+
+    /// Trilinear interpolation between cell centers, `p` going from 0 to 1 across
+    /// the grid.
+    pub fn sample(&self, p: Point3) -> f32 {
+        let mut base = [0usize; 3];
+        let mut frac = [0.0f32; 3];
+
+        for a in 0..3 {
+            let x = (p[a] * self.dims[a] as f32 - 0.5).clamp(0.0, (self.dims[a] - 1) as f32);
+            base[a] = (x as usize).min(self.dims[a].saturating_sub(2));
+            frac[a] = x - base[a] as f32;
+        }
+
+        let mut density = 0.0;
+
+        for (i, j, k) in (0..8).map(|c| (c & 1, (c >> 1) & 1, (c >> 2) & 1)) {
+            let x = (base[0] + i).min(self.dims[0] - 1);
+            let y = (base[1] + j).min(self.dims[1] - 1);
+            let z = (base[2] + k).min(self.dims[2] - 1);
+
+            let weight = (if i == 1 { frac[0] } else { 1.0 - frac[0] })
+                * (if j == 1 { frac[1] } else { 1.0 - frac[1] })
+                * (if k == 1 { frac[2] } else { 1.0 - frac[2] });
+
+            density += weight * self.data[(z * self.dims[1] + y) * self.dims[0] + x];
+        }
+
+        density
+    }
+}
+
+/// A heterogeneous medium like a cloud or an explosion. The grid is stretched over
+/// the bounding box of `boundary` and only the inside of the boundary is filled.
+/// Scattering is sampled with delta tracking against the densest cell, which
+/// shadow rays go through too: a ray gets through as often as the light would.
+pub struct GridVolume {
+    pub boundary: Arc<dyn Hittable>,
+    pub grid: DensityGrid,
+    pub bounds: AABB,
+    /// Turns grid values into extinction per unit length.
+    pub density_scale: f32,
+    pub phase_function: Arc<dyn Material>,
+}
+
+impl GridVolume {
+    /// Fails if the boundary has no bounding box or a flat one, which the grid
+    /// can't be stretched over.
+    pub fn new(
+        boundary: Arc<dyn Hittable>,
+        grid: DensityGrid,
+        density_scale: f32,
+        albedo: Arc<dyn Texture>,
+    ) -> Result<Arc<Self>, GridError> {
+        let boundary_error = |message: &str| GridError::Boundary {
+            message: message.to_string(),
+        };
+
+        let mut bounds = AABB::default();
+        if !boundary.bounding_box(0.0, f32::MAX, &mut bounds) {
+            return Err(boundary_error("boundary has no bounding box"));
+        }
+
+        let size = bounds.maximum - bounds.minimum;
+        if !(size.x > 0.0 && size.y > 0.0 && size.z > 0.0) {
+            return Err(boundary_error(
+                "boundary is flat, the grid needs some depth",
+            ));
+        }
+
+        Ok(Arc::new(Self {
+            boundary,
+            grid,
+            bounds,
+            density_scale,
+            phase_function: Isotropic::new(albedo),
+        }))
+    }
+
+    pub fn density(&self, p: Point3) -> f32 {
+        let size = self.bounds.maximum - self.bounds.minimum;
+        let local = p - self.bounds.minimum;
+
+        self.density_scale
+            * self.grid.sample(Point3::new(
+                local.x / size.x,
+                local.y / size.y,
+                local.z / size.z,
+            ))
+    }
+
+    /// Part of the ray inside the boundary, assuming the boundary is convex.
+    fn interval(
+        &self,
+        ray: &Ray,
+        t_min: f32,
+        t_max: f32,
+        sampler: &mut dyn Sampler,
+    ) -> Option<(f32, f32)> {
+        if self.grid.max_density <= 0.0 || self.density_scale <= 0.0 {
+            return None;
+        }
+
+        let mut rec1 = HitRecord::default();
+        let mut rec2 = HitRecord::default();
+
+        if !self
+            .boundary
+            .hit(ray, -f32::INFINITY, f32::INFINITY, &mut rec1, sampler)
+        {
+            return None;
+        }
+
+        if !self
+            .boundary
+            .hit(ray, rec1.t + 0.0001, f32::INFINITY, &mut rec2, sampler)
+        {
+            return None;
+        }
+
+        let t_enter = rec1.t.max(t_min).max(0.0);
+        let t_exit = rec2.t.min(t_max);
+
+        if t_enter >= t_exit {
+            None
+        } else {
+            Some((t_enter, t_exit))
+        }
+    }
+}
+
+impl Hittable for GridVolume {
+    fn hit(
+        &self,
+        ray: &Ray,
+        t_min: f32,
+        t_max: f32,
+        rec: &mut HitRecord,
+        sampler: &mut dyn Sampler,
+    ) -> bool {
+        let (t_enter, t_exit) = match self.interval(ray, t_min, t_max, sampler) {
+            Some(interval) => interval,
+            None => return false,
+        };
+
+        let majorant = self.grid.max_density * self.density_scale;
+        let ray_length = ray.dir.length();
+        let mut t = t_enter;
+
+        // free flights against the majorant, each collision is real with the
+        // probability of the local density and fictitious otherwise
+        loop {
+            t -= (1.0 - sampler.get_1d()).ln() / (majorant * ray_length);
+
+            if t >= t_exit {
+                return false;
+            }
+
+            if sampler.get_1d() * majorant < self.density(ray.at(t)) {
+                break;
+            }
+        }
+
+        rec.t = t;
+        rec.p = ray.at(t);
+        // arbitrary, the phase function doesn't use them
+        rec.normal = Vec3::new(1.0, 0.0, 0.0);
+        rec.front_face = true;
+        rec.material = Some(self.phase_function.clone());
+
+        true
+    }
+
+    fn bounding_box(&self, _time0: f32, _time1: f32, output_box: &mut AABB) -> bool {
+        *output_box = self.bounds;
+
+        true
+    }
+}
@@ -1,6 +1,10 @@
+use std::sync::OnceLock;
+
 use image::{Pixel, Rgb};
-use rand::prelude::*;
 
+use super::sampler::*;
+use super::tone_mapping::*;
+use super::vec2::*;
 use super::vec3::*;
 
 pub fn clamp(x: f32, min: f32, max: f32) -> f32 {
@@ -10,71 +14,110 @@ pub fn clamp(x: f32, min: f32, max: f32) -> f32 {
     if x > max {
         return max;
     }
-    return x;
+    x
 }
 
+/// Averages the samples and encodes them as clamped sRGB.
 pub fn to_rgb(color: &Color, samples_per_pixel: u32) -> Vec<u8> {
-    let mut r = color.x;
-    let mut g = color.y;
-    let mut b = color.z;
-
     let scale = 1.0 / samples_per_pixel as f32;
-    r = (scale * r).sqrt();
-    g = (scale * g).sqrt();
-    b = (scale * b).sqrt();
+    let c = DisplaySettings::default().encode(&(*color * scale));
 
     vec![
-        (256.0 * clamp(r, 0.0, 0.999)) as u8,
-        (256.0 * clamp(g, 0.0, 0.999)) as u8,
-        (256.0 * clamp(b, 0.0, 0.999)) as u8,
+        (255.0 * c.x).round() as u8,
+        (255.0 * c.y).round() as u8,
+        (255.0 * c.z).round() as u8,
     ]
 }
 
+/// Decodes an sRGB pixel into linear color.
 pub fn from_rgb(pixel: &Rgb<u8>) -> Color {
-    let channels = pixel.channels();
+    // textures are read several times per sample, decode each value once
+    static SRGB_TO_LINEAR: OnceLock<[f32; 256]> = OnceLock::new();
+    let table = SRGB_TO_LINEAR.get_or_init(|| {
+        let mut table = [0.0; 256];
+        for (i, v) in table.iter_mut().enumerate() {
+            *v = srgb_to_linear(i as f32 / 255.0);
+        }
+        table
+    });
 
-    let r = channels[0] as f32 / 255.0;
-    let g = channels[1] as f32 / 255.0;
-    let b = channels[2] as f32 / 255.0;
-    Color::new(r, g, b)
+    let channels = pixel.channels();
+    Color::new(
+        table[channels[0] as usize],
+        table[channels[1] as usize],
+        table[channels[2] as usize],
+    )
 }
 
-pub fn random_f32() -> f32 {
-    let mut rnd = rand::thread_rng();
+pub fn random_f32(sampler: &mut dyn Sampler) -> f32 {
+    sampler.get_1d()
+}
 
-    rnd.gen()
+pub fn random_f32_range(sampler: &mut dyn Sampler, from: f32, to: f32) -> f32 {
+    from + (to - from) * sampler.get_1d()
 }
 
-pub fn random_f32_range(from: f32, to: f32) -> f32 {
-    let mut rnd = rand::thread_rng();
+pub fn random_in_unit_sphere(sampler: &mut dyn Sampler) -> Vec3 {
+    let direction = random_unit_vector(sampler);
 
-    rnd.gen_range(from..to)
+    sampler.get_1d().cbrt() * direction
 }
 
-pub fn random_in_unit_sphere() -> Vec3 {
-    loop {
-        let p = Vec3::random_range(-1.0, 1.0);
-        if p.length_squared() < 1.0 {
-            return p;
-        }
-    }
-}
+/// Every direction equally likely, out of a single 2D sample.
+pub fn random_unit_vector(sampler: &mut dyn Sampler) -> Vec3 {
+    let u = sampler.get_2d();
+    let z = 1.0 - 2.0 * u.x;
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = 2.0 * std::f32::consts::PI * u.y;
 
-pub fn random_unit_vector() -> Vec3 {
-    random_in_unit_sphere().unit_vector()
+    Vec3::new(r * phi.cos(), r * phi.sin(), z)
 }
 
-pub fn random_in_unit_disc() -> Vec3 {
-    let mut rnd = rand::thread_rng();
+/// Shirley and Chiu's concentric mapping of a 2D sample onto the disc, which keeps
+/// neighbouring samples close so their stratification carries over to the lens.
+pub fn random_in_unit_disc(sampler: &mut dyn Sampler) -> Vec3 {
+    let u = sampler.get_2d();
+    let (a, b) = (2.0 * u.x - 1.0, 2.0 * u.y - 1.0);
 
-    loop {
-        let p = Vec3::new(rnd.gen_range(-1.0..1.0), rnd.gen_range(-1.0..1.0), 0.0);
-        if p.length_squared() < 1.0 {
-            return p;
-        }
+    if a == 0.0 && b == 0.0 {
+        return Vec3::zero();
     }
+
+    let quarter = std::f32::consts::FRAC_PI_4;
+    let (r, theta) = if a.abs() > b.abs() {
+        (a, quarter * (b / a))
+    } else {
+        (b, 2.0 * quarter - quarter * (a / b))
+    };
+
+    Vec3::new(r * theta.cos(), r * theta.sin(), 0.0)
 }
 
 pub fn degrees_to_radians(degrees: f32) -> f32 {
     degrees * std::f32::consts::PI / 180.0
 }
+
+/// Direction around the z axis with a density of cos(theta) / pi.
+pub fn random_cosine_direction(sampler: &mut dyn Sampler) -> Vec3 {
+    let Vec2 { x: r1, y: r2 } = sampler.get_2d();
+    let z = (1.0 - r2).sqrt();
+
+    let phi = 2.0 * std::f32::consts::PI * r1;
+    let x = phi.cos() * r2.sqrt();
+    let y = phi.sin() * r2.sqrt();
+
+    Vec3::new(x, y, z)
+}
+
+/// Direction around the z axis within the cone subtended by a sphere of `radius`
+/// at `distance_squared` from the origin, all directions equally likely.
+pub fn random_to_sphere(sampler: &mut dyn Sampler, radius: f32, distance_squared: f32) -> Vec3 {
+    let Vec2 { x: r1, y: r2 } = sampler.get_2d();
+    let z = 1.0 + r2 * ((1.0 - radius * radius / distance_squared).sqrt() - 1.0);
+
+    let phi = 2.0 * std::f32::consts::PI * r1;
+    let x = phi.cos() * (1.0 - z * z).sqrt();
+    let y = phi.sin() * (1.0 - z * z).sqrt();
+
+    Vec3::new(x, y, z)
+}
@@ -1,3 +1,4 @@
+use nalgebra as na;
 use partial_min_max::{max as fmax, min as fmin};
 
 use std::default::Default;
@@ -7,6 +8,7 @@ use super::aabb::*;
 use super::helpers::*;
 use super::material::*;
 use super::ray::*;
+use super::sampler::*;
 use super::vec3::*;
 
 #[derive(Default)]
@@ -19,6 +21,9 @@ pub struct HitRecord {
     pub front_face: bool,
     pub material: Option<Arc<dyn Material>>,
     pub override_color: Option<Color>,
+    /// Top level object that was hit, numbered from one by the scene. Zero for
+    /// objects without a number.
+    pub object_id: u32,
 }
 
 impl HitRecord {
@@ -38,8 +43,84 @@ impl HitRecord {
 }
 
 pub trait Hittable: Sync + Send {
-    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32, record: &mut HitRecord) -> bool;
+    fn hit(
+        &self,
+        ray: &Ray,
+        t_min: f32,
+        t_max: f32,
+        record: &mut HitRecord,
+        sampler: &mut dyn Sampler,
+    ) -> bool;
     fn bounding_box(&self, time0: f32, time1: f32, output_box: &mut AABB) -> bool;
+
+    /// Density, in solid angle, of `random` picking `direction` from `origin`.
+    /// Objects that can't be sampled as lights return zero.
+    fn pdf_value(&self, _origin: &Point3, _direction: &Vec3, _sampler: &mut dyn Sampler) -> f32 {
+        0.0
+    }
+
+    /// A direction from `origin` towards a random point of the object.
+    fn random(&self, _origin: &Point3, _sampler: &mut dyn Sampler) -> Vec3 {
+        Vec3::new(1.0, 0.0, 0.0)
+    }
+}
+
+/// Numbers the hits of a top level object, for the object ID output.
+pub struct ObjectId {
+    pub hittable: Arc<dyn Hittable>,
+    pub id: u32,
+}
+
+impl ObjectId {
+    pub fn new(hittable: Arc<dyn Hittable>, id: u32) -> Self {
+        Self { hittable, id }
+    }
+
+    pub fn arc(self) -> Arc<Self> {
+        Arc::new(self)
+    }
+
+    /// Numbers every object of a scene from one, in order.
+    pub fn number(objects: &[Arc<dyn Hittable>]) -> Vec<Arc<dyn Hittable>> {
+        objects
+            .iter()
+            .enumerate()
+            .map(|(i, object)| -> Arc<dyn Hittable> {
+                ObjectId::new(object.clone(), i as u32 + 1).arc()
+            })
+            .collect()
+    }
+}
+
+impl Hittable for ObjectId {
+    fn hit(
+        &self,
+        ray: &Ray,
+        t_min: f32,
+        t_max: f32,
+        rec: &mut HitRecord,
+        sampler: &mut dyn Sampler,
+    ) -> bool {
+        if !self.hittable.hit(ray, t_min, t_max, rec, sampler) {
+            return false;
+        }
+
+        rec.object_id = self.id;
+
+        true
+    }
+
+    fn bounding_box(&self, time0: f32, time1: f32, output_box: &mut AABB) -> bool {
+        self.hittable.bounding_box(time0, time1, output_box)
+    }
+
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3, sampler: &mut dyn Sampler) -> f32 {
+        self.hittable.pdf_value(origin, direction, sampler)
+    }
+
+    fn random(&self, origin: &Point3, sampler: &mut dyn Sampler) -> Vec3 {
+        self.hittable.random(origin, sampler)
+    }
 }
 
 pub struct Translate {
@@ -61,10 +142,17 @@ impl Translate {
 }
 
 impl Hittable for Translate {
-    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord) -> bool {
-        let moved_r = Ray::new(ray.orig - self.displacement, ray.dir);
-
-        if !self.hittable.hit(&moved_r, t_min, t_max, rec) {
+    fn hit(
+        &self,
+        ray: &Ray,
+        t_min: f32,
+        t_max: f32,
+        rec: &mut HitRecord,
+        sampler: &mut dyn Sampler,
+    ) -> bool {
+        let moved_r = Ray::with_time(ray.orig - self.displacement, ray.dir, ray.time);
+
+        if !self.hittable.hit(&moved_r, t_min, t_max, rec, sampler) {
             return false;
         }
 
@@ -86,6 +174,16 @@ impl Hittable for Translate {
 
         true
     }
+
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3, sampler: &mut dyn Sampler) -> f32 {
+        self.hittable
+            .pdf_value(&(*origin - self.displacement), direction, sampler)
+    }
+
+    fn random(&self, origin: &Point3, sampler: &mut dyn Sampler) -> Vec3 {
+        self.hittable
+            .random(&(*origin - self.displacement), sampler)
+    }
 }
 
 pub struct RotateY {
@@ -101,15 +199,12 @@ impl RotateY {
         let radians = degrees_to_radians(angle);
         let sin_theta = radians.sin();
         let cos_theta = radians.cos();
+        // moving objects are bounded over every time, like in the BVH builds
         let mut bbox = AABB::default();
-        let has_box = hittable.bounding_box(0.0, 1.0, &mut bbox);
+        let has_box = hittable.bounding_box(0.0, f32::MAX, &mut bbox);
 
-        let mut min = Point3::new(std::f32::INFINITY, std::f32::INFINITY, std::f32::INFINITY);
-        let mut max = Point3::new(
-            -std::f32::INFINITY,
-            -std::f32::INFINITY,
-            -std::f32::INFINITY,
-        );
+        let mut min = Point3::new(f32::INFINITY, f32::INFINITY, f32::INFINITY);
+        let mut max = Point3::new(-f32::INFINITY, -f32::INFINITY, -f32::INFINITY);
 
         for i in 0..2 {
             for j in 0..2 {
@@ -145,10 +240,25 @@ impl RotateY {
     pub fn arc(self) -> Arc<Self> {
         Arc::new(self)
     }
+
+    fn to_local(&self, v: Vec3) -> Vec3 {
+        Vec3::new(
+            self.cos_theta * v.x - self.sin_theta * v.z,
+            v.y,
+            self.sin_theta * v.x + self.cos_theta * v.z,
+        )
+    }
 }
 
 impl Hittable for RotateY {
-    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord) -> bool {
+    fn hit(
+        &self,
+        ray: &Ray,
+        t_min: f32,
+        t_max: f32,
+        rec: &mut HitRecord,
+        sampler: &mut dyn Sampler,
+    ) -> bool {
         let mut origin = ray.orig;
         let mut direction = ray.dir;
 
@@ -158,9 +268,9 @@ impl Hittable for RotateY {
         direction[0] = self.cos_theta * ray.dir[0] - self.sin_theta * ray.dir[2];
         direction[2] = self.sin_theta * ray.dir[0] + self.cos_theta * ray.dir[2];
 
-        let rotated_r = Ray::new(origin, direction);
+        let rotated_r = Ray::with_time(origin, direction, ray.time);
 
-        if !self.hittable.hit(&rotated_r, t_min, t_max, rec) {
+        if !self.hittable.hit(&rotated_r, t_min, t_max, rec, sampler) {
             return false;
         }
 
@@ -176,11 +286,362 @@ impl Hittable for RotateY {
         rec.p = p;
         rec.set_face_normal(&rotated_r, normal);
 
-        return true;
+        true
+    }
+
+    fn bounding_box(&self, _time0: f32, _time1: f32, output_box: &mut AABB) -> bool {
+        *output_box = self.bbox;
+        self.has_box
+    }
+
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3, sampler: &mut dyn Sampler) -> f32 {
+        self.hittable
+            .pdf_value(&self.to_local(*origin), &self.to_local(*direction), sampler)
+    }
+
+    fn random(&self, origin: &Point3, sampler: &mut dyn Sampler) -> Vec3 {
+        let v = self.hittable.random(&self.to_local(*origin), sampler);
+
+        Vec3::new(
+            self.cos_theta * v.x + self.sin_theta * v.z,
+            v.y,
+            -self.sin_theta * v.x + self.cos_theta * v.z,
+        )
+    }
+}
+
+/// An object placed with an arbitrary affine transform. Rays are moved into object
+/// space instead of the object into world space, so a single BVH can be shared by
+/// any number of instances.
+pub struct Instance {
+    pub hittable: Arc<dyn Hittable>,
+    pub transform: na::Matrix4<f32>,
+    pub inverse: na::Matrix4<f32>,
+    /// Inverse transpose of the linear part.
+    pub normal_transform: na::Matrix3<f32>,
+    pub has_box: bool,
+    pub bbox: AABB,
+}
+
+impl Instance {
+    /// Panics if `transform` is not invertible.
+    pub fn new(hittable: Arc<dyn Hittable>, transform: na::Matrix4<f32>) -> Self {
+        let inverse = transform
+            .try_inverse()
+            .expect("instance transform must be invertible");
+        let normal_transform = inverse.fixed_slice::<3, 3>(0, 0).transpose();
+
+        // moving objects are bounded over every time, like in the BVH builds
+        let mut bbox = AABB::default();
+        let has_box = hittable.bounding_box(0.0, f32::MAX, &mut bbox);
+
+        Self {
+            hittable,
+            transform,
+            inverse,
+            normal_transform,
+            has_box,
+            bbox: transform_box(&bbox, &transform),
+        }
+    }
+
+    pub fn arc(self) -> Arc<Self> {
+        Arc::new(self)
+    }
+}
+
+impl Hittable for Instance {
+    fn hit(
+        &self,
+        ray: &Ray,
+        t_min: f32,
+        t_max: f32,
+        rec: &mut HitRecord,
+        sampler: &mut dyn Sampler,
+    ) -> bool {
+        if !self
+            .hittable
+            .hit(&local_ray(ray, &self.inverse), t_min, t_max, rec, sampler)
+        {
+            return false;
+        }
+
+        world_record(rec, &self.transform, &self.normal_transform);
+
+        true
     }
 
     fn bounding_box(&self, _time0: f32, _time1: f32, output_box: &mut AABB) -> bool {
         *output_box = self.bbox;
         self.has_box
     }
+
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3, sampler: &mut dyn Sampler) -> f32 {
+        let local_origin: Point3 = self.inverse.transform_point(&(*origin).into()).into();
+        let local_direction =
+            Vec3::from(self.inverse.transform_vector(&(*direction).into())).unit_vector();
+
+        let pdf = self
+            .hittable
+            .pdf_value(&local_origin, &local_direction, sampler);
+
+        // a linear map stretches solid angle around w by |det| / |Mw|^3
+        let linear = self.transform.fixed_slice::<3, 3>(0, 0);
+        let stretched = (linear * na::Vector3::from(local_direction)).norm();
+
+        pdf * stretched.powi(3) / linear.determinant().abs()
+    }
+
+    fn random(&self, origin: &Point3, sampler: &mut dyn Sampler) -> Vec3 {
+        let local_origin: Point3 = self.inverse.transform_point(&(*origin).into()).into();
+        let direction = self.hittable.random(&local_origin, sampler);
+
+        self.transform.transform_vector(&direction.into()).into()
+    }
+}
+
+/// Placement of an animated object at a point in time.
+#[derive(Debug, Copy, Clone)]
+pub struct Keyframe {
+    pub time: f32,
+    pub translation: Vec3,
+    pub rotation: na::UnitQuaternion<f32>,
+    pub scale: Vec3,
+}
+
+impl Keyframe {
+    pub fn new(time: f32) -> Self {
+        Self {
+            time,
+            translation: Vec3::zero(),
+            rotation: na::UnitQuaternion::identity(),
+            scale: Vec3::new(1.0, 1.0, 1.0),
+        }
+    }
+
+    /// Scales, then rotates, then translates.
+    pub fn matrix(&self) -> na::Matrix4<f32> {
+        na::Matrix4::new_translation(&self.translation.into())
+            * self.rotation.to_homogeneous()
+            * na::Matrix4::new_nonuniform_scaling(&self.scale.into())
+    }
+
+    /// Linear interpolation of the translation and scale, rotations take the
+    /// shortest path.
+    pub fn lerp(&self, other: &Keyframe, t: f32) -> Keyframe {
+        let rotation = self
+            .rotation
+            .try_slerp(&other.rotation, t, 1.0e-6)
+            .unwrap_or(if t < 0.5 {
+                self.rotation
+            } else {
+                other.rotation
+            });
+
+        Keyframe {
+            time: self.time + t * (other.time - self.time),
+            translation: self.translation + t * (other.translation - self.translation),
+            rotation,
+            scale: self.scale + t * (other.scale - self.scale),
+        }
+    }
+}
+
+/// An object moving between keyframes over time. Before the first and after
+/// the last keyframe it holds still.
+pub struct Animated {
+    pub hittable: Arc<dyn Hittable>,
+    pub keyframes: Vec<Keyframe>,
+}
+
+impl Animated {
+    /// Panics if there are no keyframes.
+    pub fn new(hittable: Arc<dyn Hittable>, mut keyframes: Vec<Keyframe>) -> Self {
+        if keyframes.is_empty() {
+            panic!("Animated needs at least one keyframe");
+        }
+
+        keyframes.sort_by(|a, b| {
+            a.time
+                .partial_cmp(&b.time)
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+
+        Self {
+            hittable,
+            keyframes,
+        }
+    }
+
+    pub fn arc(self) -> Arc<Self> {
+        Arc::new(self)
+    }
+
+    pub fn keyframe(&self, time: f32) -> Keyframe {
+        let next = self.keyframes.iter().position(|k| k.time > time);
+
+        match next {
+            Some(0) => self.keyframes[0],
+            Some(i) => {
+                let (a, b) = (&self.keyframes[i - 1], &self.keyframes[i]);
+                a.lerp(b, (time - a.time) / (b.time - a.time))
+            }
+            None => self.keyframes[self.keyframes.len() - 1],
+        }
+    }
+}
+
+/// Placements sampled between every two keyframes when bounding the motion.
+/// Rotations bend the paths of the corners away from the straight lines between
+/// samples, which the boxes are padded for.
+const ANIMATION_BOX_SAMPLES: usize = 16;
+
+impl Hittable for Animated {
+    fn hit(
+        &self,
+        ray: &Ray,
+        t_min: f32,
+        t_max: f32,
+        rec: &mut HitRecord,
+        sampler: &mut dyn Sampler,
+    ) -> bool {
+        let transform = self.keyframe(ray.time).matrix();
+        let inverse = match transform.try_inverse() {
+            Some(inverse) => inverse,
+            None => return false,
+        };
+
+        if !self
+            .hittable
+            .hit(&local_ray(ray, &inverse), t_min, t_max, rec, sampler)
+        {
+            return false;
+        }
+
+        world_record(
+            rec,
+            &transform,
+            &inverse.fixed_slice::<3, 3>(0, 0).transpose(),
+        );
+
+        true
+    }
+
+    fn bounding_box(&self, time0: f32, time1: f32, output_box: &mut AABB) -> bool {
+        let mut bbox = AABB::default();
+        if !self.hittable.bounding_box(time0, time1, &mut bbox) {
+            return false;
+        }
+
+        let first = self.keyframes[0].time;
+        let last = self.keyframes[self.keyframes.len() - 1].time;
+        let start = time0.max(first).min(last);
+        let end = time1.max(first).min(last);
+
+        let largest = |v: Vec3| v.x.abs().max(v.y.abs()).max(v.z.abs());
+        let farthest = |a: f32, b: f32| a.abs().max(b.abs());
+        let corner = Vec3::new(
+            farthest(bbox.minimum.x, bbox.maximum.x),
+            farthest(bbox.minimum.y, bbox.maximum.y),
+            farthest(bbox.minimum.z, bbox.maximum.z),
+        )
+        .length();
+
+        let mut output = transform_box(&bbox, &self.keyframe(start).matrix());
+
+        for pair in self.keyframes.windows(2) {
+            let (a, b) = (&pair[0], &pair[1]);
+            let (from, to) = (start.max(a.time), end.min(b.time));
+            if from >= to {
+                continue;
+            }
+
+            let mut segment = transform_box(
+                &bbox,
+                &a.lerp(b, (from - a.time) / (b.time - a.time)).matrix(),
+            );
+            for i in 1..=ANIMATION_BOX_SAMPLES {
+                let time = from + (to - from) * i as f32 / ANIMATION_BOX_SAMPLES as f32;
+                let placement = a.lerp(b, (time - a.time) / (b.time - a.time));
+                segment = segment & transform_box(&bbox, &placement.matrix());
+            }
+
+            // A corner strays from the line between two samples by at most an
+            // eighth of its acceleration times the square of the step. The
+            // rotation turns at a steady rate and the scale grows at one, so
+            // over the keyframes the acceleration stays below
+            // corner * (angle^2 * scale + 2 * angle * growth).
+            let angle = a.rotation.angle_to(&b.rotation);
+            let scale = largest(a.scale).max(largest(b.scale));
+            let growth = largest(b.scale - a.scale);
+            let step = (to - from) / (b.time - a.time) / ANIMATION_BOX_SAMPLES as f32;
+            let pad = step * step / 8.0 * corner * angle * (angle * scale + 2.0 * growth);
+
+            let pad = Vec3::new(pad, pad, pad);
+            output = output & AABB::new(segment.minimum - pad, segment.maximum + pad);
+        }
+
+        *output_box = output;
+
+        true
+    }
+}
+
+/// Bounds the eight transformed corners of `bbox`.
+fn transform_box(bbox: &AABB, transform: &na::Matrix4<f32>) -> AABB {
+    let mut min = Point3::new(f32::INFINITY, f32::INFINITY, f32::INFINITY);
+    let mut max = Point3::new(-f32::INFINITY, -f32::INFINITY, -f32::INFINITY);
+
+    for i in 0..2 {
+        for j in 0..2 {
+            for k in 0..2 {
+                let x = if i == 0 {
+                    bbox.minimum.x
+                } else {
+                    bbox.maximum.x
+                };
+                let y = if j == 0 {
+                    bbox.minimum.y
+                } else {
+                    bbox.maximum.y
+                };
+                let z = if k == 0 {
+                    bbox.minimum.z
+                } else {
+                    bbox.maximum.z
+                };
+
+                let tester: Vec3 = transform.transform_point(&na::Point3::new(x, y, z)).into();
+
+                for c in 0..3 {
+                    min[c] = fmin(min[c], tester[c]);
+                    max[c] = fmax(max[c], tester[c]);
+                }
+            }
+        }
+    }
+
+    AABB::new(min, max)
+}
+
+/// Moves a ray into the space of a transformed object. The direction is left
+/// unnormalized so t is the same in both spaces.
+fn local_ray(ray: &Ray, inverse: &na::Matrix4<f32>) -> Ray {
+    Ray::with_time(
+        inverse.transform_point(&ray.orig.into()).into(),
+        inverse.transform_vector(&ray.dir.into()).into(),
+        ray.time,
+    )
+}
+
+fn world_record(
+    rec: &mut HitRecord,
+    transform: &na::Matrix4<f32>,
+    normal_transform: &na::Matrix3<f32>,
+) {
+    // the normal already faces the ray, the inverse transpose keeps it that way
+    let normal: Vec3 = (normal_transform * na::Vector3::from(rec.normal)).into();
+
+    rec.p = transform.transform_point(&rec.p.into()).into();
+    rec.normal = normal.unit_vector();
 }
@@ -3,6 +3,8 @@ use std::sync::Arc;
 use super::aabb::*;
 use super::hittable::*;
 use super::ray::*;
+use super::sampler::*;
+use super::vec3::*;
 
 #[derive(Default)]
 pub struct HittableList {
@@ -17,21 +19,32 @@ impl HittableList {
     pub fn add(&mut self, item: Arc<dyn Hittable>) {
         self.items.push(item)
     }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
 }
 
 impl Hittable for HittableList {
-    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32, record: &mut HitRecord) -> bool {
+    fn hit(
+        &self,
+        ray: &Ray,
+        t_min: f32,
+        t_max: f32,
+        record: &mut HitRecord,
+        sampler: &mut dyn Sampler,
+    ) -> bool {
         let mut hit_anything = false;
         let mut closest_so_far = t_max;
 
         for hittable in &self.items {
-            if hittable.hit(ray, t_min, closest_so_far, record) {
+            if hittable.hit(ray, t_min, closest_so_far, record, sampler) {
                 hit_anything = true;
                 closest_so_far = record.t;
             }
         }
 
-        return hit_anything;
+        hit_anything
     }
 
     fn bounding_box(&self, time0: f32, time1: f32, output_box: &mut AABB) -> bool {
@@ -52,4 +65,44 @@ impl Hittable for HittableList {
 
         true
     }
+
+    /// Items are picked evenly, whatever their size.
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3, sampler: &mut dyn Sampler) -> f32 {
+        items_pdf_value(&self.items, origin, direction, sampler)
+    }
+
+    fn random(&self, origin: &Point3, sampler: &mut dyn Sampler) -> Vec3 {
+        items_random(&self.items, origin, sampler)
+    }
+}
+
+/// Average density of picking one of `items` evenly and sampling it.
+pub fn items_pdf_value(
+    items: &[Arc<dyn Hittable>],
+    origin: &Point3,
+    direction: &Vec3,
+    sampler: &mut dyn Sampler,
+) -> f32 {
+    if items.is_empty() {
+        return 0.0;
+    }
+
+    let weight = 1.0 / items.len() as f32;
+    items
+        .iter()
+        .map(|item| weight * item.pdf_value(origin, direction, sampler))
+        .sum()
+}
+
+pub fn items_random(
+    items: &[Arc<dyn Hittable>],
+    origin: &Point3,
+    sampler: &mut dyn Sampler,
+) -> Vec3 {
+    if items.is_empty() {
+        return Vec3::new(1.0, 0.0, 0.0);
+    }
+
+    let i = ((sampler.get_1d() * items.len() as f32) as usize).min(items.len() - 1);
+    items[i].random(origin, sampler)
 }
@@ -0,0 +1,164 @@
+use std::sync::Arc;
+
+use super::aabb::*;
+use super::bvh::*;
+use super::hittable::*;
+use super::hittable_list::*;
+use super::ray::*;
+use super::sampler::*;
+use super::vec3::*;
+
+/// Upper bound of the tree height, see `MAX_SAH_DEPTH`.
+const STACK_SIZE: usize = 64;
+
+/// A BVH stored as a flat array of nodes in depth first order, with the
+/// primitives of each leaf next to each other.
+pub struct LinearBVH {
+    pub nodes: Vec<LinearNode>,
+    pub primitives: Vec<Arc<dyn Hittable>>,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct LinearNode {
+    pub aabb: AABB,
+    /// First primitive of a leaf, or the second child of an interior node. The
+    /// first child always follows its parent.
+    pub offset: u32,
+    /// Number of primitives, zero for interior nodes.
+    pub count: u16,
+    pub axis: u8,
+}
+
+impl LinearBVH {
+    pub fn new(objects: &[Arc<dyn Hittable>], time0: f32, time1: f32) -> Self {
+        Self::build(objects, time0, time1, &BVHOptions::default()).0
+    }
+
+    pub fn build(
+        objects: &[Arc<dyn Hittable>],
+        time0: f32,
+        time1: f32,
+        options: &BVHOptions,
+    ) -> (Self, BVHStats) {
+        let tree = BuildNode::new(objects, time0, time1, options);
+        let stats = tree.stats();
+
+        let mut bvh = Self {
+            nodes: Vec::with_capacity(stats.node_count),
+            primitives: Vec::with_capacity(stats.primitive_count),
+        };
+        bvh.flatten(tree, objects);
+
+        (bvh, stats)
+    }
+
+    fn flatten(&mut self, node: BuildNode, objects: &[Arc<dyn Hittable>]) {
+        match node {
+            BuildNode::Leaf {
+                aabb,
+                objects: leaf,
+            } if leaf.len() <= u16::MAX as usize => {
+                self.nodes.push(LinearNode {
+                    aabb,
+                    offset: self.primitives.len() as u32,
+                    count: leaf.len() as u16,
+                    axis: 0,
+                });
+                self.primitives
+                    .extend(leaf.iter().map(|&i| objects[i].clone()));
+            }
+            BuildNode::Leaf { .. } => panic!("Too many primitives in a bvh leaf"),
+            BuildNode::Interior {
+                aabb,
+                axis,
+                left,
+                right,
+            } => {
+                let index = self.nodes.len();
+                self.nodes.push(LinearNode {
+                    aabb,
+                    offset: 0,
+                    count: 0,
+                    axis: axis as u8,
+                });
+
+                self.flatten(*left, objects);
+                self.nodes[index].offset = self.nodes.len() as u32;
+                self.flatten(*right, objects);
+            }
+        }
+    }
+}
+
+impl Hittable for LinearBVH {
+    fn hit(
+        &self,
+        ray: &Ray,
+        t_min: f32,
+        t_max: f32,
+        record: &mut HitRecord,
+        sampler: &mut dyn Sampler,
+    ) -> bool {
+        let inv_dir = Vec3::new(1.0 / ray.dir.x, 1.0 / ray.dir.y, 1.0 / ray.dir.z);
+        let dir_is_neg = [inv_dir.x < 0.0, inv_dir.y < 0.0, inv_dir.z < 0.0];
+
+        let mut stack = [0u32; STACK_SIZE];
+        let mut stack_size = 0;
+        let mut current = 0;
+
+        let mut hit_anything = false;
+        let mut closest_so_far = t_max;
+
+        loop {
+            let node = &self.nodes[current];
+
+            if node
+                .aabb
+                .hit_inverse(ray.orig, inv_dir, t_min, closest_so_far)
+            {
+                if node.count > 0 {
+                    let first = node.offset as usize;
+                    for primitive in &self.primitives[first..first + node.count as usize] {
+                        if primitive.hit(ray, t_min, closest_so_far, record, sampler) {
+                            hit_anything = true;
+                            closest_so_far = record.t;
+                        }
+                    }
+                } else {
+                    // visit the near child first so the far one can be culled by closest_so_far
+                    if dir_is_neg[node.axis as usize] {
+                        stack[stack_size] = current as u32 + 1;
+                        current = node.offset as usize;
+                    } else {
+                        stack[stack_size] = node.offset;
+                        current += 1;
+                    }
+                    stack_size += 1;
+                    continue;
+                }
+            }
+
+            if stack_size == 0 {
+                break;
+            }
+            stack_size -= 1;
+            current = stack[stack_size] as usize;
+        }
+
+        hit_anything
+    }
+
+    fn bounding_box(&self, _time0: f32, _time1: f32, output_box: &mut AABB) -> bool {
+        *output_box = self.nodes[0].aabb;
+
+        true
+    }
+
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3, sampler: &mut dyn Sampler) -> f32 {
+        items_pdf_value(&self.primitives, origin, direction, sampler)
+    }
+
+    fn random(&self, origin: &Point3, sampler: &mut dyn Sampler) -> Vec3 {
+        items_random(&self.primitives, origin, sampler)
+    }
+}
@@ -1,27 +1,40 @@
 use std::sync::Arc;
 
-use rand::prelude::*;
+use serde::Deserialize;
 
 use super::helpers::*;
 use super::hittable::*;
+use super::pdf::*;
 use super::ray::*;
+use super::sampler::*;
 use super::texture::*;
 use super::vec3::*;
 
+#[derive(Debug, Copy, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum Side {
     Front,
     Back,
     Double,
 }
 
+/// What a material does with an incoming ray. Specular materials give the one ray
+/// to follow, the others the distribution of their bounces to sample or mix with
+/// light sampling.
+#[derive(Default)]
+pub struct ScatterRecord {
+    pub attenuation: Color,
+    pub specular_ray: Option<Ray>,
+    pub pdf: Option<Box<dyn Pdf>>,
+}
+
 pub trait Material: Sync + Send {
     fn scatter(
         &self,
         ray_in: &Ray,
         hit_record: &HitRecord,
-        alb: &mut Color,
-        scattered: &mut Ray,
-        pdf: &mut f32,
+        srec: &mut ScatterRecord,
+        sampler: &mut dyn Sampler,
     ) -> bool;
 
     fn scattering_pdf(&self, _ray_in: &Ray, _hit_record: &HitRecord, _scattered: &mut Ray) -> f32 {
@@ -46,21 +59,14 @@ impl Lambertian {
 impl Material for Lambertian {
     fn scatter(
         &self,
-        _: &Ray,
+        _ray_in: &Ray,
         rec: &HitRecord,
-        alb: &mut Color,
-        scattered: &mut Ray,
-        pdf: &mut f32,
+        srec: &mut ScatterRecord,
+        _sampler: &mut dyn Sampler,
     ) -> bool {
-        let mut scatter_direction = rec.normal + random_unit_vector();
-
-        if scatter_direction.near_zero() {
-            scatter_direction = rec.normal;
-        }
-
-        *scattered = Ray::new(rec.p, scatter_direction.unit_vector());
-        *alb = self.albedo.value(rec.u, rec.v, &rec.p);
-        *pdf = rec.normal.dot(scattered.dir) / std::f32::consts::PI;
+        srec.attenuation = self.albedo.value(rec.u, rec.v, &rec.p);
+        srec.specular_ray = None;
+        srec.pdf = Some(Box::new(CosinePdf::new(rec.normal)));
         true
     }
 
@@ -91,16 +97,18 @@ impl Material for Metal {
         &self,
         ray_in: &Ray,
         rec: &HitRecord,
-        attenuation: &mut Color,
-        scattered: &mut Ray,
-        _pdf: &mut f32,
+        srec: &mut ScatterRecord,
+        sampler: &mut dyn Sampler,
     ) -> bool {
         let reflected = ray_in.dir.unit_vector().reflect(rec.normal);
-        *scattered = Ray::new(
+        let scattered = Ray::with_time(
             rec.p,
-            reflected + self.fuzz * random_in_unit_sphere().unit_vector(),
+            reflected + self.fuzz * random_unit_vector(sampler),
+            ray_in.time,
         );
-        *attenuation = self.albedo;
+        srec.attenuation = self.albedo;
+        srec.specular_ray = Some(scattered);
+        srec.pdf = None;
 
         scattered.dir.dot(rec.normal) > 0.0
     }
@@ -124,11 +132,10 @@ impl Material for Dielectric {
         &self,
         ray_in: &Ray,
         rec: &HitRecord,
-        attenuation: &mut Color,
-        scattered: &mut Ray,
-        _pdf: &mut f32,
+        srec: &mut ScatterRecord,
+        sampler: &mut dyn Sampler,
     ) -> bool {
-        *attenuation = Color::new(1.0, 1.0, 1.0);
+        srec.attenuation = Color::new(1.0, 1.0, 1.0);
         let refraction_ratio = if rec.front_face {
             1.0 / self.index_of_refraction
         } else {
@@ -141,16 +148,16 @@ impl Material for Dielectric {
         let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
 
         let cannot_refract = refraction_ratio * sin_theta > 1.0;
-        let direction;
-
-        if cannot_refract || reflectance(cos_theta, refraction_ratio) > rand::thread_rng().gen() {
-            direction = unit_direction.reflect(rec.normal);
-        } else {
-            direction = unit_direction.refract(rec.normal, refraction_ratio);
-        }
-
-        *scattered = Ray::new(rec.p, direction);
-        return true;
+        let direction =
+            if cannot_refract || reflectance(cos_theta, refraction_ratio) > sampler.get_1d() {
+                unit_direction.reflect(rec.normal)
+            } else {
+                unit_direction.refract(rec.normal, refraction_ratio)
+            };
+
+        srec.specular_ray = Some(Ray::with_time(rec.p, direction, ray_in.time));
+        srec.pdf = None;
+        true
     }
 }
 
@@ -182,9 +189,8 @@ impl Material for DiffuseLight {
         &self,
         _: &Ray,
         _rec: &HitRecord,
-        _attenuation: &mut Color,
-        _scattered: &mut Ray,
-        _pdf: &mut f32,
+        _srec: &mut ScatterRecord,
+        _sampler: &mut dyn Sampler,
     ) -> bool {
         false
     }
@@ -204,6 +210,38 @@ impl Material for DiffuseLight {
     }
 }
 
+/// Phase function of participating media, scattering evenly in all directions.
+pub struct Isotropic {
+    pub albedo: Arc<dyn Texture>,
+}
+
+impl Isotropic {
+    pub fn new(albedo: Arc<dyn Texture>) -> Arc<Self> {
+        Arc::new(Self { albedo })
+    }
+}
+
+impl Material for Isotropic {
+    fn scatter(
+        &self,
+        _ray_in: &Ray,
+        rec: &HitRecord,
+        srec: &mut ScatterRecord,
+        _sampler: &mut dyn Sampler,
+    ) -> bool {
+        srec.attenuation = self.albedo.value(rec.u, rec.v, &rec.p);
+        srec.specular_ray = None;
+        srec.pdf = Some(Box::new(SpherePdf));
+        true
+    }
+
+    fn scattering_pdf(&self, _ray_in: &Ray, _rec: &HitRecord, _scattered: &mut Ray) -> f32 {
+        1.0 / (4.0 * std::f32::consts::PI)
+    }
+}
+
+#[derive(Debug, Copy, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum DebugTarget {
     Normal,
     Face,
@@ -228,9 +266,8 @@ impl Material for DebugMaterial {
         &self,
         _: &Ray,
         _rec: &HitRecord,
-        _attenuation: &mut Color,
-        _scattered: &mut Ray,
-        _pdf: &mut f32,
+        _srec: &mut ScatterRecord,
+        _sampler: &mut dyn Sampler,
     ) -> bool {
         false
     }
@@ -253,5 +290,5 @@ fn reflectance(cosine: f32, ref_idx: f32) -> f32 {
     // Use Schlick's approximation for reflectance.
     let mut r0 = (1.0 - ref_idx) / (1.0 + ref_idx);
     r0 = r0 * r0;
-    return r0 + (1.0 - r0) * (1.0 - cosine).powf(5.0);
+    r0 + (1.0 - r0) * (1.0 - cosine).powf(5.0)
 }
@@ -1,104 +1,297 @@
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
+use nalgebra as na;
 use serde::{Deserialize, Serialize};
-use serde_json;
+use serde_json::Value;
 
 use super::attribute::*;
-use super::bvh::*;
 use super::hittable::*;
+use super::linear_bvh::*;
 use super::material::*;
+use super::obj::NormalMode;
 use super::triangle::*;
 use super::vec2::*;
 use super::vec3::*;
 
+/// Flat vertex attributes with an optional triangle index, as extracted from three.js
+/// geometries. Normals and uvs may be omitted.
 #[derive(Serialize, Deserialize, Debug)]
-struct Mesh {
-    position: Vec<f32>,
-    normal: Vec<f32>,
-    uv: Vec<f32>,
-    index: Vec<u16>,
+pub struct MeshData {
+    pub position: Vec<f32>,
+    #[serde(default)]
+    pub normal: Option<Vec<f32>>,
+    #[serde(default)]
+    pub uv: Option<Vec<f32>>,
+    #[serde(default)]
+    pub index: Option<Vec<u32>>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-struct Donut {
-    pub cup: Mesh,
-    pub plate: Mesh,
+pub struct JsonMeshOptions {
+    /// Key of the mesh when the file holds several of them, like `cup` in donut.json.
+    pub name: Option<String>,
+    pub transform: na::Matrix4<f32>,
+    pub normals: NormalMode,
 }
 
-pub fn bake_cup_mesh(material: Arc<dyn Material>) -> Arc<dyn Hittable> {
-    let data = include_str!("../../assets/donut.json");
-
-    let donut: Donut = serde_json::from_str(data).unwrap();
-
-    let mut trinagles: Vec<Arc<dyn Hittable>> = vec![];
-
-    let shift = Point3::new(0.0, 0.9, 0.0);
+impl Default for JsonMeshOptions {
+    fn default() -> Self {
+        Self {
+            name: None,
+            transform: na::Matrix4::identity(),
+            normals: NormalMode::Smooth,
+        }
+    }
+}
 
-    for i in (0..donut.cup.index.len()).step_by(3) {
-        let ai = donut.cup.index[i] as usize;
-        let bi = donut.cup.index[i + 1] as usize;
-        let ci = donut.cup.index[i + 2] as usize;
+#[derive(Debug)]
+pub enum MeshError {
+    Io {
+        path: PathBuf,
+        source: io::Error,
+    },
+    Parse {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+    Invalid {
+        path: PathBuf,
+        message: String,
+    },
+}
 
-        let a = Point3::from_array(&donut.cup.position, ai * 3) * 20.0 + shift;
-        let b = Point3::from_array(&donut.cup.position, bi * 3) * 20.0 + shift;
-        let c = Point3::from_array(&donut.cup.position, ci * 3) * 20.0 + shift;
+impl fmt::Display for MeshError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MeshError::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            MeshError::Parse { path, source } => write!(f, "{}: {}", path.display(), source),
+            MeshError::Invalid { path, message } => write!(f, "{}: {}", path.display(), message),
+        }
+    }
+}
 
-        let na = Point3::from_array(&donut.cup.normal, ai * 3);
-        let nb = Point3::from_array(&donut.cup.normal, bi * 3);
-        let nc = Point3::from_array(&donut.cup.normal, ci * 3);
+impl Error for MeshError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            MeshError::Io { source, .. } => Some(source),
+            MeshError::Parse { source, .. } => Some(source),
+            MeshError::Invalid { .. } => None,
+        }
+    }
+}
 
-        let ta = Point2::from_array(&donut.cup.uv, ai * 2);
-        let tb = Point2::from_array(&donut.cup.uv, bi * 2);
-        let tc = Point2::from_array(&donut.cup.uv, ci * 2);
+pub fn load_json_mesh<P: AsRef<Path>>(
+    path: P,
+    material: Arc<dyn Material>,
+    options: &JsonMeshOptions,
+) -> Result<Arc<dyn Hittable>, MeshError> {
+    let path = path.as_ref();
+    let invalid = |message: String| MeshError::Invalid {
+        path: path.to_path_buf(),
+        message,
+    };
+
+    let source = fs::read_to_string(path).map_err(|source| MeshError::Io {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    let mut value: Value = serde_json::from_str(&source).map_err(|source| MeshError::Parse {
+        path: path.to_path_buf(),
+        source,
+    })?;
+
+    if let Some(name) = &options.name {
+        value = value
+            .get_mut(name)
+            .map(Value::take)
+            .ok_or_else(|| invalid(format!("no mesh named `{}`", name)))?;
+    }
 
-        let position = Attribute::new(a, b, c);
-        let normal = Attribute::new(na, nb, nc);
-        let uv = Attribute::new(ta, tb, tc);
+    let data: MeshData = serde_json::from_value(value).map_err(|source| MeshError::Parse {
+        path: path.to_path_buf(),
+        source,
+    })?;
 
-        let triangle = Triangle::new(position, normal, uv, material.clone());
+    let triangles = mesh_triangles(&data, material, options).map_err(invalid)?;
 
-        trinagles.push(triangle);
+    if triangles.is_empty() {
+        return Err(invalid(String::from("no triangles")));
     }
 
-    Arc::new(BVHNode::new(&trinagles, 0.0, f32::MAX))
+    Ok(Arc::new(LinearBVH::new(&triangles, 0.0, f32::MAX)))
 }
 
-pub fn bake_monkey_mesh(material: Arc<dyn Material>) -> Arc<dyn Hittable> {
-    let data = include_str!("../../assets/monkey.json");
-
-    let monkey: Mesh = serde_json::from_str(data).unwrap();
-
-    let mut trinagles: Vec<Arc<dyn Hittable>> = vec![];
-
-    let shift = Point3::new(0.0, 1.0, 0.0);
+/// Validates the attribute arrays against each other and the index, then builds
+/// transformed triangles.
+pub fn mesh_triangles(
+    data: &MeshData,
+    material: Arc<dyn Material>,
+    options: &JsonMeshOptions,
+) -> Result<Vec<Arc<dyn Hittable>>, String> {
+    if !data.position.len().is_multiple_of(3) {
+        return Err(format!(
+            "position has {} values, expected a multiple of 3",
+            data.position.len()
+        ));
+    }
 
-    for i in (0..monkey.index.len()).step_by(3) {
-        let ai = monkey.index[i] as usize;
-        let bi = monkey.index[i + 1] as usize;
-        let ci = monkey.index[i + 2] as usize;
+    let vertex_count = data.position.len() / 3;
+
+    if let Some(normal) = &data.normal {
+        if normal.len() != vertex_count * 3 {
+            return Err(format!(
+                "normal has {} values, expected {} for {} vertices",
+                normal.len(),
+                vertex_count * 3,
+                vertex_count
+            ));
+        }
+    }
 
-        let a = Point3::from_array(&monkey.position, ai * 3) + shift;
-        let b = Point3::from_array(&monkey.position, bi * 3) + shift;
-        let c = Point3::from_array(&monkey.position, ci * 3) + shift;
+    if let Some(uv) = &data.uv {
+        if uv.len() != vertex_count * 2 {
+            return Err(format!(
+                "uv has {} values, expected {} for {} vertices",
+                uv.len(),
+                vertex_count * 2,
+                vertex_count
+            ));
+        }
+    }
 
-        let na = Point3::from_array(&monkey.normal, ai * 3);
-        let nb = Point3::from_array(&monkey.normal, bi * 3);
-        let nc = Point3::from_array(&monkey.normal, ci * 3);
+    let faces: Vec<[usize; 3]> = match &data.index {
+        Some(index) => {
+            if !index.len().is_multiple_of(3) {
+                return Err(format!(
+                    "index has {} values, expected a multiple of 3",
+                    index.len()
+                ));
+            }
+
+            if let Some((i, v)) = index
+                .iter()
+                .enumerate()
+                .find(|(_, v)| **v as usize >= vertex_count)
+            {
+                return Err(format!(
+                    "index[{}] is {}, but there are only {} vertices",
+                    i, v, vertex_count
+                ));
+            }
+
+            index
+                .chunks_exact(3)
+                .map(|f| [f[0] as usize, f[1] as usize, f[2] as usize])
+                .collect()
+        }
+        None => {
+            if !vertex_count.is_multiple_of(3) {
+                return Err(format!(
+                    "{} vertices without an index don't form triangles",
+                    vertex_count
+                ));
+            }
+
+            (0..vertex_count / 3)
+                .map(|f| [f * 3, f * 3 + 1, f * 3 + 2])
+                .collect()
+        }
+    };
+
+    let transform = &options.transform;
+    let normal_transform = transform
+        .try_inverse()
+        .unwrap_or_else(na::Matrix4::identity)
+        .fixed_slice::<3, 3>(0, 0)
+        .transpose();
+    // mirroring transforms flip the winding, swap it back so faces keep facing out
+    let mirrored = transform.fixed_slice::<3, 3>(0, 0).determinant() < 0.0;
+
+    let positions: Vec<Vec3> = (0..vertex_count)
+        .map(|i| {
+            let p = Point3::from_array(&data.position, i * 3);
+            let p = transform.transform_point(&na::Point3::new(p.x, p.y, p.z));
+            Vec3::new(p.x, p.y, p.z)
+        })
+        .collect();
+
+    let normals: Vec<Vec3> = match (&data.normal, options.normals) {
+        (Some(normal), _) => (0..vertex_count)
+            .map(|i| {
+                let n = Vec3::from_array(normal, i * 3);
+                let n = normal_transform * na::Vector3::new(n.x, n.y, n.z);
+                Vec3::new(n.x, n.y, n.z).unit_vector()
+            })
+            .collect(),
+        (None, NormalMode::Smooth) => vertex_normals(&positions, &faces),
+        (None, NormalMode::Flat) => vec![],
+    };
+
+    let mut triangles: Vec<Arc<dyn Hittable>> = vec![];
+
+    for face in faces {
+        let [ai, mut bi, mut ci] = face;
+        if mirrored {
+            std::mem::swap(&mut bi, &mut ci);
+        }
+
+        let position = Attribute::new(positions[ai], positions[bi], positions[ci]);
+        let face_normal = (position.b - position.a).cross(position.c - position.a);
+
+        // degenerate faces have no normal and can't be hit anyway
+        if face_normal.length_squared() == 0.0 {
+            continue;
+        }
+
+        let normal = if normals.is_empty() {
+            let n = face_normal.unit_vector();
+            Attribute::new(n, n, n)
+        } else {
+            Attribute::new(normals[ai], normals[bi], normals[ci])
+        };
+
+        let uv = match &data.uv {
+            Some(uv) => Attribute::new(
+                Point2::from_array(uv, ai * 2),
+                Point2::from_array(uv, bi * 2),
+                Point2::from_array(uv, ci * 2),
+            ),
+            None => Attribute::default(),
+        };
+
+        triangles.push(Triangle::new(position, normal, uv, material.clone()));
+    }
 
-        let ta = Point2::from_array(&monkey.uv, ai * 2);
-        let tb = Point2::from_array(&monkey.uv, bi * 2);
-        let tc = Point2::from_array(&monkey.uv, ci * 2);
+    Ok(triangles)
+}
 
-        let position = Attribute::new(a, b, c);
-        let normal = Attribute::new(na, nb, nc);
-        let uv = Attribute::new(ta, tb, tc);
+/// Area weighted average of the normals of the faces sharing each vertex.
+pub fn vertex_normals(positions: &[Vec3], faces: &[[usize; 3]]) -> Vec<Vec3> {
+    let mut normals = vec![Vec3::zero(); positions.len()];
 
-        let triangle = Triangle::new(position, normal, uv, material.clone());
+    for &[a, b, c] in faces {
+        let pa = positions[a];
+        let normal = (positions[b] - pa).cross(positions[c] - pa);
 
-        trinagles.push(triangle);
+        normals[a] += normal;
+        normals[b] += normal;
+        normals[c] += normal;
     }
 
-    Arc::new(BVHNode::new(&trinagles, 0.0, f32::MAX))
+    normals
+        .into_iter()
+        .map(|n| {
+            if n.length_squared() == 0.0 {
+                n
+            } else {
+                n.unit_vector()
+            }
+        })
+        .collect()
 }
 
 pub fn xy_rect(
@@ -108,7 +301,7 @@ pub fn xy_rect(
     y1: f32,
     k: f32,
     material: Arc<dyn Material>,
-) -> Arc<BVHNode> {
+) -> Arc<LinearBVH> {
     let a = Point3::new(x0, y0, k);
     let b = Point3::new(x1, y1, k);
     let c = Point3::new(x0, y1, k);
@@ -148,7 +341,7 @@ pub fn xy_rect(
         material.clone(),
     );
 
-    Arc::new(BVHNode::new(&[t1, t2], 0.0, f32::MAX))
+    Arc::new(LinearBVH::new(&[t1, t2], 0.0, f32::MAX))
 }
 
 pub fn yz_rect(
@@ -158,7 +351,7 @@ pub fn yz_rect(
     z1: f32,
     k: f32,
     material: Arc<dyn Material>,
-) -> Arc<BVHNode> {
+) -> Arc<LinearBVH> {
     let a = Point3::new(k, y0, z0);
     let b = Point3::new(k, y1, z0);
     let c = Point3::new(k, y1, z1);
@@ -198,7 +391,7 @@ pub fn yz_rect(
         material.clone(),
     );
 
-    Arc::new(BVHNode::new(&[t1, t2], 0.0, f32::MAX))
+    Arc::new(LinearBVH::new(&[t1, t2], 0.0, f32::MAX))
 }
 
 pub fn xz_rect(
@@ -208,7 +401,7 @@ pub fn xz_rect(
     z1: f32,
     k: f32,
     material: Arc<dyn Material>,
-) -> Arc<BVHNode> {
+) -> Arc<LinearBVH> {
     let a = Point3::new(x0, k, z0);
     let b = Point3::new(x0, k, z1);
     let c = Point3::new(x1, k, z1);
@@ -248,7 +441,7 @@ pub fn xz_rect(
         material.clone(),
     );
 
-    Arc::new(BVHNode::new(&[t1, t2], 0.0, f32::MAX))
+    Arc::new(LinearBVH::new(&[t1, t2], 0.0, f32::MAX))
 }
 
 pub fn bake_box(p0: Vec3, p1: Vec3, material: Arc<dyn Material>) -> Arc<dyn Hittable> {
@@ -261,5 +454,5 @@ pub fn bake_box(p0: Vec3, p1: Vec3, material: Arc<dyn Material>) -> Arc<dyn Hitt
         yz_rect(p1.y, p0.y, p0.z, p1.z, p0.x, material.clone()),
     ];
 
-    Arc::new(BVHNode::new(&sides, 0.0, f32::MAX))
+    Arc::new(LinearBVH::new(&sides, 0.0, f32::MAX))
 }
@@ -1,31 +1,65 @@
 pub mod aabb;
+pub mod adaptive;
+pub mod aov;
 pub mod attribute;
+pub mod background;
 pub mod bvh;
 pub mod camera;
+pub mod checkpoint;
+pub mod constant_medium;
+pub mod denoise;
+pub mod filter;
+pub mod framebuffer;
+pub mod gltf_import;
+pub mod grid_volume;
 pub mod helpers;
 pub mod hittable;
 pub mod hittable_list;
+pub mod linear_bvh;
 pub mod material;
 pub mod meshes;
+pub mod obj;
+pub mod onb;
+pub mod pdf;
+pub mod perlin;
 pub mod ray;
+pub mod sampler;
 pub mod sphere;
 pub mod texture;
+pub mod tone_mapping;
 pub mod triangle;
 pub mod vec2;
 pub mod vec3;
 
 pub use aabb::*;
+pub use adaptive::*;
+pub use aov::*;
 pub use attribute::*;
+pub use background::*;
 pub use bvh::*;
 pub use camera::*;
+pub use checkpoint::*;
+pub use constant_medium::*;
+pub use denoise::*;
+pub use filter::*;
+pub use framebuffer::*;
+pub use gltf_import::*;
+pub use grid_volume::*;
 pub use helpers::*;
 pub use hittable::*;
 pub use hittable_list::*;
+pub use linear_bvh::*;
 pub use material::*;
 pub use meshes::*;
+pub use obj::*;
+pub use onb::*;
+pub use pdf::*;
+pub use perlin::*;
 pub use ray::*;
+pub use sampler::*;
 pub use sphere::*;
 pub use texture::*;
+pub use tone_mapping::*;
 pub use triangle::*;
 pub use vec2::*;
 pub use vec3::*;
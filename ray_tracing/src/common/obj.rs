@@ -0,0 +1,466 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use image::ImageError;
+use serde::Deserialize;
+
+use super::attribute::*;
+use super::hittable::*;
+use super::linear_bvh::*;
+use super::material::*;
+use super::meshes::vertex_normals;
+use super::texture::*;
+use super::triangle::*;
+use super::vec2::*;
+use super::vec3::*;
+
+/// How vertex normals are produced for faces that don't reference any.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NormalMode {
+    Flat,
+    Smooth,
+}
+
+pub struct ObjOptions {
+    pub normals: NormalMode,
+    /// Replaces every material from the MTL files when set.
+    pub material: Option<Arc<dyn Material>>,
+}
+
+impl Default for ObjOptions {
+    fn default() -> Self {
+        Self {
+            normals: NormalMode::Smooth,
+            material: None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ObjError {
+    Io {
+        path: PathBuf,
+        source: io::Error,
+    },
+    Parse {
+        path: PathBuf,
+        line: usize,
+        message: String,
+    },
+    Texture {
+        path: PathBuf,
+        source: ImageError,
+    },
+    Empty {
+        path: PathBuf,
+    },
+}
+
+impl fmt::Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ObjError::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            ObjError::Parse {
+                path,
+                line,
+                message,
+            } => write!(f, "{}:{}: {}", path.display(), line, message),
+            ObjError::Texture { path, source } => write!(f, "{}: {}", path.display(), source),
+            ObjError::Empty { path } => write!(f, "{}: no faces", path.display()),
+        }
+    }
+}
+
+impl Error for ObjError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ObjError::Io { source, .. } => Some(source),
+            ObjError::Texture { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+struct FaceVertex {
+    position: usize,
+    uv: Option<usize>,
+    normal: Option<usize>,
+}
+
+struct Face {
+    vertices: [FaceVertex; 3],
+    material: Option<String>,
+}
+
+/// Loads a Wavefront OBJ file with its MTL libraries. Polygons are triangulated
+/// as fans, missing normals are generated according to `options.normals`.
+pub fn load_obj<P: AsRef<Path>>(
+    path: P,
+    options: &ObjOptions,
+) -> Result<Arc<dyn Hittable>, ObjError> {
+    let path = path.as_ref();
+    let source = read(path)?;
+    let base_dir = path.parent().unwrap_or_else(|| Path::new(""));
+
+    let mut positions: Vec<Vec3> = vec![];
+    let mut uvs: Vec<Vec2> = vec![];
+    let mut normals: Vec<Vec3> = vec![];
+    let mut faces: Vec<Face> = vec![];
+    let mut libraries: Vec<PathBuf> = vec![];
+    let mut current_material: Option<String> = None;
+
+    for (i, line) in source.lines().enumerate() {
+        let parse_error = |message: String| ObjError::Parse {
+            path: path.to_path_buf(),
+            line: i + 1,
+            message,
+        };
+
+        let mut tokens = line.split_whitespace();
+        let keyword = match tokens.next() {
+            Some(keyword) if !keyword.starts_with('#') => keyword,
+            _ => continue,
+        };
+        let args: Vec<&str> = tokens.collect();
+
+        match keyword {
+            "v" => positions.push(parse_vec3(&args).map_err(parse_error)?),
+            "vn" => normals.push(parse_vec3(&args).map_err(parse_error)?),
+            "vt" => {
+                let u = parse_f32(args.first().copied()).map_err(&parse_error)?;
+                let v = match args.get(1) {
+                    Some(v) => parse_f32(Some(v)).map_err(&parse_error)?,
+                    None => 0.0,
+                };
+                uvs.push(Vec2::new(u, v));
+            }
+            "f" => {
+                if args.len() < 3 {
+                    return Err(parse_error(String::from(
+                        "a face needs at least three vertices",
+                    )));
+                }
+
+                let vertices = args
+                    .iter()
+                    .map(|arg| parse_face_vertex(arg, positions.len(), uvs.len(), normals.len()))
+                    .collect::<Result<Vec<FaceVertex>, String>>()
+                    .map_err(parse_error)?;
+
+                for k in 1..vertices.len() - 1 {
+                    faces.push(Face {
+                        vertices: [vertices[0], vertices[k], vertices[k + 1]],
+                        material: current_material.clone(),
+                    });
+                }
+            }
+            "usemtl" => current_material = args.first().map(|name| name.to_string()),
+            "mtllib" => libraries.extend(args.iter().map(|name| base_dir.join(name))),
+            _ => {}
+        }
+    }
+
+    if faces.is_empty() {
+        return Err(ObjError::Empty {
+            path: path.to_path_buf(),
+        });
+    }
+
+    let materials = match options.material {
+        Some(_) => HashMap::new(),
+        None => {
+            let mut materials = HashMap::new();
+            for library in &libraries {
+                load_mtl(library, &mut materials)?;
+            }
+            materials
+        }
+    };
+
+    let default_material: Arc<dyn Material> = match &options.material {
+        Some(material) => material.clone(),
+        None => Lambertian::new(SolidColor::new(Color::new(0.8, 0.8, 0.8))),
+    };
+
+    let smooth_normals = match options.normals {
+        NormalMode::Smooth => {
+            let position_faces: Vec<[usize; 3]> = faces
+                .iter()
+                .map(|f| {
+                    let [a, b, c] = f.vertices;
+                    [a.position, b.position, c.position]
+                })
+                .collect();
+            vertex_normals(&positions, &position_faces)
+        }
+        NormalMode::Flat => vec![],
+    };
+
+    let mut triangles: Vec<Arc<dyn Hittable>> = vec![];
+
+    for face in &faces {
+        let [a, b, c] = face.vertices;
+        let position = Attribute::new(
+            positions[a.position],
+            positions[b.position],
+            positions[c.position],
+        );
+        let face_normal = (position.b - position.a).cross(position.c - position.a);
+
+        // degenerate faces have no normal and can't be hit anyway
+        if face_normal.length_squared() == 0.0 {
+            continue;
+        }
+
+        let face_normal = face_normal.unit_vector();
+        let vertex_normal = |v: FaceVertex| match v.normal {
+            Some(n) => normals[n],
+            None if options.normals == NormalMode::Smooth => smooth_normals[v.position],
+            None => face_normal,
+        };
+        let vertex_uv = |v: FaceVertex| v.uv.map(|t| uvs[t]).unwrap_or_default();
+
+        let material = face
+            .material
+            .as_ref()
+            .and_then(|name| materials.get(name))
+            .cloned()
+            .unwrap_or_else(|| default_material.clone());
+
+        triangles.push(Triangle::new(
+            position,
+            Attribute::new(vertex_normal(a), vertex_normal(b), vertex_normal(c)),
+            Attribute::new(vertex_uv(a), vertex_uv(b), vertex_uv(c)),
+            material,
+        ));
+    }
+
+    if triangles.is_empty() {
+        return Err(ObjError::Empty {
+            path: path.to_path_buf(),
+        });
+    }
+
+    Ok(Arc::new(LinearBVH::new(&triangles, 0.0, f32::MAX)))
+}
+
+#[derive(Debug)]
+struct MtlMaterial {
+    diffuse: Color,
+    specular: Color,
+    emission: Color,
+    shininess: f32,
+    index_of_refraction: f32,
+    dissolve: f32,
+    illumination: u32,
+    roughness: Option<f32>,
+    metallic: f32,
+    diffuse_map: Option<PathBuf>,
+}
+
+impl Default for MtlMaterial {
+    fn default() -> Self {
+        Self {
+            diffuse: Color::new(0.8, 0.8, 0.8),
+            specular: Color::zero(),
+            emission: Color::zero(),
+            shininess: 0.0,
+            index_of_refraction: 1.5,
+            dissolve: 1.0,
+            illumination: 2,
+            roughness: None,
+            metallic: 0.0,
+            diffuse_map: None,
+        }
+    }
+}
+
+fn load_mtl(
+    path: &Path,
+    materials: &mut HashMap<String, Arc<dyn Material>>,
+) -> Result<(), ObjError> {
+    let source = read(path)?;
+    let base_dir = path.parent().unwrap_or_else(|| Path::new(""));
+
+    let mut parsed: Vec<(String, MtlMaterial)> = vec![];
+
+    for (i, line) in source.lines().enumerate() {
+        let parse_error = |message: String| ObjError::Parse {
+            path: path.to_path_buf(),
+            line: i + 1,
+            message,
+        };
+
+        let mut tokens = line.split_whitespace();
+        let keyword = match tokens.next() {
+            Some(keyword) if !keyword.starts_with('#') => keyword,
+            _ => continue,
+        };
+        let args: Vec<&str> = tokens.collect();
+
+        if keyword == "newmtl" {
+            let name = args
+                .first()
+                .ok_or_else(|| parse_error(String::from("newmtl without a name")))?;
+            parsed.push((name.to_string(), MtlMaterial::default()));
+            continue;
+        }
+
+        let material = match parsed.last_mut() {
+            Some((_, material)) => material,
+            None => continue,
+        };
+
+        match keyword {
+            "Kd" => material.diffuse = parse_vec3(&args).map_err(parse_error)?,
+            "Ks" => material.specular = parse_vec3(&args).map_err(parse_error)?,
+            "Ke" => material.emission = parse_vec3(&args).map_err(parse_error)?,
+            "Ns" => material.shininess = parse_f32(args.first().copied()).map_err(parse_error)?,
+            "Ni" => {
+                material.index_of_refraction =
+                    parse_f32(args.first().copied()).map_err(parse_error)?
+            }
+            "d" => material.dissolve = parse_f32(args.first().copied()).map_err(parse_error)?,
+            "Tr" => {
+                material.dissolve = 1.0 - parse_f32(args.first().copied()).map_err(parse_error)?
+            }
+            "illum" => {
+                material.illumination = args
+                    .first()
+                    .and_then(|v| v.parse().ok())
+                    .ok_or_else(|| parse_error(String::from("expected an illumination model")))?
+            }
+            "Pr" => {
+                material.roughness = Some(parse_f32(args.first().copied()).map_err(parse_error)?)
+            }
+            "Pm" => material.metallic = parse_f32(args.first().copied()).map_err(parse_error)?,
+            // options like `-bm 1.0` come before the file name, which is always last
+            "map_Kd" => material.diffuse_map = args.last().map(|name| base_dir.join(name)),
+            _ => {}
+        }
+    }
+
+    for (name, material) in parsed {
+        materials.insert(name, to_material(&material)?);
+    }
+
+    Ok(())
+}
+
+fn to_material(mtl: &MtlMaterial) -> Result<Arc<dyn Material>, ObjError> {
+    if !mtl.emission.near_zero() {
+        return Ok(DiffuseLight::new(mtl.emission).arc());
+    }
+
+    if mtl.dissolve < 1.0 || matches!(mtl.illumination, 4 | 6 | 7 | 9) {
+        let index_of_refraction = if mtl.index_of_refraction > 1.0 {
+            mtl.index_of_refraction
+        } else {
+            1.5
+        };
+        return Ok(Dielectric::new(index_of_refraction));
+    }
+
+    if mtl.metallic > 0.5 || mtl.illumination == 3 {
+        let albedo = if mtl.metallic > 0.5 || mtl.specular.near_zero() {
+            mtl.diffuse
+        } else {
+            mtl.specular
+        };
+        let fuzz = mtl
+            .roughness
+            .unwrap_or(1.0 - mtl.shininess / 1000.0)
+            .clamp(0.0, 1.0);
+        return Ok(Metal::new(albedo, fuzz));
+    }
+
+    let albedo: Arc<dyn Texture> = match &mtl.diffuse_map {
+        Some(map) => ImageTexture::open(
+            &map.to_string_lossy(),
+            TextureFiltering::Linear,
+            TextureFlip::FlipY,
+            Vec2::new(1.0, 1.0),
+        )
+        .map_err(|source| ObjError::Texture {
+            path: map.clone(),
+            source,
+        })?,
+        None => SolidColor::new(mtl.diffuse),
+    };
+
+    Ok(Lambertian::new(albedo))
+}
+
+fn read(path: &Path) -> Result<String, ObjError> {
+    fs::read_to_string(path).map_err(|source| ObjError::Io {
+        path: path.to_path_buf(),
+        source,
+    })
+}
+
+fn parse_f32(value: Option<&str>) -> Result<f32, String> {
+    let value = value.ok_or_else(|| String::from("expected a number"))?;
+
+    value
+        .parse()
+        .map_err(|_| format!("invalid number `{}`", value))
+}
+
+fn parse_vec3(args: &[&str]) -> Result<Vec3, String> {
+    Ok(Vec3::new(
+        parse_f32(args.first().copied())?,
+        parse_f32(args.get(1).copied())?,
+        parse_f32(args.get(2).copied())?,
+    ))
+}
+
+fn parse_face_vertex(
+    arg: &str,
+    position_count: usize,
+    uv_count: usize,
+    normal_count: usize,
+) -> Result<FaceVertex, String> {
+    let mut parts = arg.split('/');
+
+    let position = resolve_index(parts.next(), position_count, "position")?
+        .ok_or_else(|| format!("face vertex `{}` has no position", arg))?;
+    let uv = resolve_index(parts.next(), uv_count, "texture coordinate")?;
+    let normal = resolve_index(parts.next(), normal_count, "normal")?;
+
+    Ok(FaceVertex {
+        position,
+        uv,
+        normal,
+    })
+}
+
+/// Turns a one-based (or negative, relative to the end) OBJ index into a zero-based one.
+fn resolve_index(value: Option<&str>, count: usize, kind: &str) -> Result<Option<usize>, String> {
+    let value = match value {
+        Some(value) if !value.is_empty() => value,
+        _ => return Ok(None),
+    };
+
+    let index: i64 = value
+        .parse()
+        .map_err(|_| format!("invalid {} index `{}`", kind, value))?;
+
+    let resolved = if index > 0 {
+        index - 1
+    } else {
+        count as i64 + index
+    };
+
+    if index == 0 || resolved < 0 || resolved >= count as i64 {
+        return Err(format!("{} index {} is out of range", kind, index));
+    }
+
+    Ok(Some(resolved as usize))
+}
@@ -0,0 +1,101 @@
+use std::f32::consts::PI;
+
+use super::helpers::*;
+use super::hittable::*;
+use super::onb::*;
+use super::sampler::*;
+use super::vec3::*;
+
+/// A distribution of directions, with densities measured in solid angle.
+pub trait Pdf {
+    fn value(&self, direction: &Vec3, sampler: &mut dyn Sampler) -> f32;
+    fn generate(&self, sampler: &mut dyn Sampler) -> Vec3;
+}
+
+/// Cosine weighted hemisphere around a normal, the distribution of Lambertian bounces.
+pub struct CosinePdf {
+    pub uvw: ONB,
+}
+
+impl CosinePdf {
+    pub fn new(w: Vec3) -> Self {
+        Self {
+            uvw: ONB::build_from_w(w),
+        }
+    }
+}
+
+impl Pdf for CosinePdf {
+    fn value(&self, direction: &Vec3, _sampler: &mut dyn Sampler) -> f32 {
+        let cosine = direction.unit_vector().dot(self.uvw.w);
+        if cosine <= 0.0 {
+            0.0
+        } else {
+            cosine / PI
+        }
+    }
+
+    fn generate(&self, sampler: &mut dyn Sampler) -> Vec3 {
+        self.uvw.local(random_cosine_direction(sampler))
+    }
+}
+
+/// Every direction equally likely.
+pub struct SpherePdf;
+
+impl Pdf for SpherePdf {
+    fn value(&self, _direction: &Vec3, _sampler: &mut dyn Sampler) -> f32 {
+        1.0 / (4.0 * PI)
+    }
+
+    fn generate(&self, sampler: &mut dyn Sampler) -> Vec3 {
+        random_unit_vector(sampler)
+    }
+}
+
+/// Directions from `origin` towards a hittable, sampled with its `random` method.
+pub struct HittablePdf<'a> {
+    pub origin: Point3,
+    pub hittable: &'a dyn Hittable,
+}
+
+impl<'a> HittablePdf<'a> {
+    pub fn new(hittable: &'a dyn Hittable, origin: Point3) -> Self {
+        Self { origin, hittable }
+    }
+}
+
+impl<'a> Pdf for HittablePdf<'a> {
+    fn value(&self, direction: &Vec3, sampler: &mut dyn Sampler) -> f32 {
+        self.hittable.pdf_value(&self.origin, direction, sampler)
+    }
+
+    fn generate(&self, sampler: &mut dyn Sampler) -> Vec3 {
+        self.hittable.random(&self.origin, sampler)
+    }
+}
+
+/// Picks either distribution with equal probability.
+pub struct MixturePdf<'a> {
+    pub p: [&'a dyn Pdf; 2],
+}
+
+impl<'a> MixturePdf<'a> {
+    pub fn new(p0: &'a dyn Pdf, p1: &'a dyn Pdf) -> Self {
+        Self { p: [p0, p1] }
+    }
+}
+
+impl<'a> Pdf for MixturePdf<'a> {
+    fn value(&self, direction: &Vec3, sampler: &mut dyn Sampler) -> f32 {
+        0.5 * self.p[0].value(direction, sampler) + 0.5 * self.p[1].value(direction, sampler)
+    }
+
+    fn generate(&self, sampler: &mut dyn Sampler) -> Vec3 {
+        if sampler.get_1d() < 0.5 {
+            self.p[0].generate(sampler)
+        } else {
+            self.p[1].generate(sampler)
+        }
+    }
+}
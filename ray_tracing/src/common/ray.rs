@@ -4,11 +4,17 @@ use super::vec3::*;
 pub struct Ray {
     pub orig: Point3,
     pub dir: Vec3,
+    /// Moment within the camera shutter interval the ray was sent at.
+    pub time: f32,
 }
 
 impl Ray {
     pub fn new(orig: Point3, dir: Vec3) -> Self {
-        Self { orig, dir }
+        Self::with_time(orig, dir, 0.0)
+    }
+
+    pub fn with_time(orig: Point3, dir: Vec3, time: f32) -> Self {
+        Self { orig, dir, time }
     }
 
     pub fn at(self, t: f32) -> Point3 {
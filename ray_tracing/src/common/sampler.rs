@@ -0,0 +1,369 @@
+use serde::Deserialize;
+
+use super::vec2::*;
+
+/// Largest f32 below one, samples have to stay in [0, 1).
+const ONE_MINUS_EPSILON: f32 = 1.0 - f32::EPSILON / 2.0;
+
+/// Supplies the random numbers of a sample, one dimension after another: the
+/// position in the pixel first, then the lens and shutter time of the camera and
+/// then whatever every bounce asks for. Values only depend on the seed, the pixel,
+/// the sample index and the dimension, so renders repeat whatever the threads do.
+pub trait Sampler {
+    /// Starts sample `index` of the pixel, from the first dimension again.
+    fn start_pixel_sample(&mut self, x: u32, y: u32, index: u32);
+
+    fn get_1d(&mut self) -> f32;
+
+    fn get_2d(&mut self) -> Vec2;
+
+    /// Position in the pixel, asked for first.
+    fn get_pixel_2d(&mut self) -> Vec2 {
+        self.get_2d()
+    }
+}
+
+/// How the samples of a pixel are spread.
+#[derive(Debug, Copy, Clone, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SamplerKind {
+    /// Every value uniform and independent, the slowest to converge.
+    Independent,
+    /// Every dimension split into one stratum per sample, jittered inside it. The
+    /// strata are laid out for the samples per pixel, which a resumed render can't
+    /// change.
+    Stratified,
+    /// The Halton sequence, with its digits scrambled differently in every pixel.
+    Halton,
+    /// Owen scrambled Sobol points, padded from the first two dimensions. Converges
+    /// best with a power of two samples per pixel, and any number of samples
+    /// carries on from the ones before.
+    #[default]
+    Sobol,
+}
+
+impl SamplerKind {
+    pub fn create(&self, samples_per_pixel: u32, seed: u64) -> Box<dyn Sampler> {
+        let state = SampleState::new(samples_per_pixel, seed);
+
+        match self {
+            SamplerKind::Independent => Box::new(IndependentSampler { state }),
+            SamplerKind::Stratified => Box::new(StratifiedSampler { state }),
+            SamplerKind::Halton => Box::new(HaltonSampler { state }),
+            SamplerKind::Sobol => Box::new(SobolSampler { state }),
+        }
+    }
+}
+
+/// Where a sampler is at, shared by all of them.
+#[derive(Debug, Clone)]
+struct SampleState {
+    samples_per_pixel: u32,
+    seed: u64,
+    pixel_hash: u64,
+    index: u32,
+    dimension: u32,
+}
+
+impl SampleState {
+    fn new(samples_per_pixel: u32, seed: u64) -> Self {
+        Self {
+            samples_per_pixel: samples_per_pixel.max(1),
+            seed,
+            pixel_hash: 0,
+            index: 0,
+            dimension: 0,
+        }
+    }
+
+    fn start(&mut self, x: u32, y: u32, index: u32) {
+        self.pixel_hash = mix_bits(self.seed ^ mix_bits(((x as u64) << 32) | y as u64));
+        self.index = index;
+        self.dimension = 0;
+    }
+
+    /// Hash of the pixel and the next dimension, which is taken.
+    fn next_hash(&mut self, dimensions: u32) -> u64 {
+        let hash = mix_bits(
+            self.pixel_hash ^ (self.dimension as u64 + 1).wrapping_mul(0x9E37_79B9_7F4A_7C15),
+        );
+        self.dimension += dimensions;
+        hash
+    }
+
+    /// Uniform value for the current sample out of a dimension hash.
+    fn uniform(&self, hash: u64) -> f32 {
+        to_unit(mix_bits(hash ^ mix_bits(self.index as u64 + 1)) as u32)
+    }
+
+    /// Sample index shuffled within the pixel, so strata aren't visited in the same
+    /// order by every dimension.
+    fn shuffled_index(&self, hash: u64) -> u32 {
+        permutation_element(
+            self.index % self.samples_per_pixel,
+            self.samples_per_pixel,
+            hash as u32,
+        )
+    }
+}
+
+pub struct IndependentSampler {
+    state: SampleState,
+}
+
+impl Sampler for IndependentSampler {
+    fn start_pixel_sample(&mut self, x: u32, y: u32, index: u32) {
+        self.state.start(x, y, index);
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        let hash = self.state.next_hash(1);
+        self.state.uniform(hash)
+    }
+
+    fn get_2d(&mut self) -> Vec2 {
+        let hash = self.state.next_hash(2);
+        Vec2::new(self.state.uniform(hash), self.state.uniform(mix_bits(hash)))
+    }
+}
+
+pub struct StratifiedSampler {
+    state: SampleState,
+}
+
+impl Sampler for StratifiedSampler {
+    fn start_pixel_sample(&mut self, x: u32, y: u32, index: u32) {
+        self.state.start(x, y, index);
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        let hash = self.state.next_hash(1);
+        let stratum = self.state.shuffled_index(hash);
+        let jitter = self.state.uniform(mix_bits(hash));
+
+        ((stratum as f32 + jitter) / self.state.samples_per_pixel as f32).min(ONE_MINUS_EPSILON)
+    }
+
+    fn get_2d(&mut self) -> Vec2 {
+        let hash = self.state.next_hash(2);
+        let (columns, rows) = grid(self.state.samples_per_pixel);
+        let stratum = self.state.shuffled_index(hash);
+        let jitter_x = self.state.uniform(mix_bits(hash));
+        let jitter_y = self.state.uniform(mix_bits(hash ^ 1));
+
+        Vec2::new(
+            (((stratum % columns) as f32 + jitter_x) / columns as f32).min(ONE_MINUS_EPSILON),
+            (((stratum / columns) as f32 + jitter_y) / rows as f32).min(ONE_MINUS_EPSILON),
+        )
+    }
+}
+
+/// Most columns and rows of a grid of `n` cells, as square as possible.
+fn grid(n: u32) -> (u32, u32) {
+    let mut columns = (n as f32).sqrt() as u32;
+    while columns > 1 && !n.is_multiple_of(columns) {
+        columns -= 1;
+    }
+    let columns = columns.max(1);
+
+    (columns, n / columns)
+}
+
+/// Bases of the Halton dimensions, further ones are sampled independently.
+const PRIMES: [u32; 64] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97,
+    101, 103, 107, 109, 113, 127, 131, 137, 139, 149, 151, 157, 163, 167, 173, 179, 181, 191, 193,
+    197, 199, 211, 223, 227, 229, 233, 239, 241, 251, 257, 263, 269, 271, 277, 281, 283, 293, 307,
+    311,
+];
+
+pub struct HaltonSampler {
+    state: SampleState,
+}
+
+impl HaltonSampler {
+    fn sample(&mut self) -> f32 {
+        let dimension = self.state.dimension as usize;
+        let hash = self.state.next_hash(1);
+
+        match PRIMES.get(dimension) {
+            Some(&base) => scrambled_radical_inverse(base, self.state.index, hash),
+            None => self.state.uniform(hash),
+        }
+    }
+}
+
+impl Sampler for HaltonSampler {
+    fn start_pixel_sample(&mut self, x: u32, y: u32, index: u32) {
+        self.state.start(x, y, index);
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        self.sample()
+    }
+
+    fn get_2d(&mut self) -> Vec2 {
+        let x = self.sample();
+        Vec2::new(x, self.sample())
+    }
+}
+
+/// Digits of `index` in `base` mirrored around the point, each digit position with
+/// its own random permutation of the digits.
+fn scrambled_radical_inverse(base: u32, index: u32, hash: u64) -> f32 {
+    let inverse_base = 1.0 / base as f32;
+    let mut inverse_base_power = 1.0;
+    let mut reversed = 0.0;
+    let mut permutation = hash;
+    let mut rest = index;
+
+    loop {
+        // a step of an LCG is enough to pick the next permutation, its high bits
+        // are random enough and it is much cheaper than hashing every position
+        permutation = permutation
+            .wrapping_mul(0x5851_f42d_4c95_7f2d)
+            .wrapping_add(0x1405_7b7e_f767_814f);
+        let digit = permutation_element(rest % base, base, (permutation >> 32) as u32);
+
+        inverse_base_power *= inverse_base;
+        reversed += digit as f32 * inverse_base_power;
+        rest /= base;
+
+        if rest == 0 {
+            break;
+        }
+    }
+
+    // the permuted zeros past the last digit add up to a uniform offset within the
+    // last digit, drawn at once instead of digit by digit
+    let tail = to_unit(mix_bits(hash ^ mix_bits(index as u64 + 1)) as u32);
+
+    (reversed + tail * inverse_base_power).min(ONE_MINUS_EPSILON)
+}
+
+pub struct SobolSampler {
+    state: SampleState,
+}
+
+impl Sampler for SobolSampler {
+    fn start_pixel_sample(&mut self, x: u32, y: u32, index: u32) {
+        self.state.start(x, y, index);
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        let hash = self.state.next_hash(1);
+        let index = shuffled_sobol_index(self.state.index, hash);
+
+        to_unit(owen_scramble(sobol_0(index), (hash >> 32) as u32))
+    }
+
+    fn get_2d(&mut self) -> Vec2 {
+        let hash = self.state.next_hash(2);
+        let index = shuffled_sobol_index(self.state.index, hash);
+        let scramble = mix_bits(hash);
+
+        Vec2::new(
+            to_unit(owen_scramble(sobol_0(index), scramble as u32)),
+            to_unit(owen_scramble(sobol_1(index), (scramble >> 32) as u32)),
+        )
+    }
+}
+
+/// Sample index shuffled differently in every dimension (Burley's shuffled Sobol
+/// sequence). Scrambling the index moves every aligned power of two block of
+/// indices onto another one, whose points are just as well spread, so the first
+/// samples stay well spread whatever the samples per pixel, and a resumed render
+/// carries on where the previous one stopped.
+fn shuffled_sobol_index(index: u32, hash: u64) -> u32 {
+    owen_scramble(index, hash as u32)
+}
+
+/// First Sobol dimension, the van der Corput sequence.
+fn sobol_0(index: u32) -> u32 {
+    index.reverse_bits()
+}
+
+/// Second Sobol dimension, whose generator matrix is Pascal's triangle mod 2.
+fn sobol_1(mut index: u32) -> u32 {
+    let mut direction = 1 << 31;
+    let mut result = 0;
+
+    while index != 0 {
+        if index & 1 != 0 {
+            result ^= direction;
+        }
+        index >>= 1;
+        direction ^= direction >> 1;
+    }
+
+    result
+}
+
+/// Nested uniform scrambling of the bits after the point, where every bit is
+/// flipped depending on the ones before it (Burley's hash based version).
+fn owen_scramble(mut v: u32, seed: u32) -> u32 {
+    v = v.reverse_bits();
+    v ^= v.wrapping_mul(0x3d20_adea);
+    v = v.wrapping_add(seed);
+    v = v.wrapping_mul((seed >> 16) | 1);
+    v ^= v.wrapping_mul(0x0552_6c56);
+    v ^= v.wrapping_mul(0x53a2_2864);
+    v.reverse_bits()
+}
+
+/// Element `i` of a random permutation of `0..l` picked by `p` (Kensler's
+/// correlated multi-jittered sampling).
+fn permutation_element(mut i: u32, l: u32, p: u32) -> u32 {
+    let mut w = l - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+
+    loop {
+        i ^= p;
+        i = i.wrapping_mul(0xe170_893d);
+        i ^= p >> 16;
+        i ^= (i & w) >> 4;
+        i ^= p >> 8;
+        i = i.wrapping_mul(0x0929_eb3f);
+        i ^= p >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | p >> 27);
+        i = i.wrapping_mul(0x6935_fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dc_b303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e50_1cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860_a3df);
+        i &= w;
+        i ^= i >> 5;
+
+        if i < l {
+            break;
+        }
+    }
+
+    let i = i + p % l;
+    if i >= l {
+        i - l
+    } else {
+        i
+    }
+}
+
+/// Finalizer of splitmix64, spreads every input bit over the whole output.
+fn mix_bits(mut v: u64) -> u64 {
+    v ^= v >> 31;
+    v = v.wrapping_mul(0x7fb5_d329_728e_a185);
+    v ^= v >> 27;
+    v = v.wrapping_mul(0x81da_def4_bc2d_d44d);
+    v ^= v >> 33;
+    v
+}
+
+fn to_unit(v: u32) -> f32 {
+    (v as f32 * (1.0 / 4_294_967_296.0)).min(ONE_MINUS_EPSILON)
+}
@@ -2,9 +2,12 @@ use std::f32::consts::PI;
 use std::sync::Arc;
 
 use super::aabb::*;
+use super::helpers::*;
 use super::hittable::*;
 use super::material::*;
+use super::onb::*;
 use super::ray::Ray;
+use super::sampler::*;
 use super::vec3::*;
 
 pub struct Sphere {
@@ -24,46 +27,184 @@ impl Sphere {
 }
 
 impl Hittable for Sphere {
-    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32, record: &mut HitRecord) -> bool {
-        let oc = ray.orig - self.center;
-        let a = ray.dir.length_squared();
-        let half_b = oc.dot(ray.dir);
-        let c = oc.length_squared() - self.radius * self.radius;
-
-        let discriminant = half_b * half_b - a * c;
-        if discriminant < 0.0 {
-            return false;
-        };
-        let sqrtd = discriminant.sqrt();
+    fn hit(
+        &self,
+        ray: &Ray,
+        t_min: f32,
+        t_max: f32,
+        record: &mut HitRecord,
+        _sampler: &mut dyn Sampler,
+    ) -> bool {
+        hit_sphere(
+            self.center,
+            self.radius,
+            &self.material,
+            ray,
+            t_min,
+            t_max,
+            record,
+        )
+    }
 
-        let mut root = (-half_b - sqrtd) / a;
-        if root < t_min || t_max < root {
-            root = (-half_b + sqrtd) / a;
-            if root < t_min || t_max < root {
-                return false;
-            }
+    fn bounding_box(&self, _time0: f32, _time1: f32, output_box: &mut AABB) -> bool {
+        *output_box = sphere_box(self.center, self.radius);
+
+        true
+    }
+
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3, sampler: &mut dyn Sampler) -> f32 {
+        let mut rec = HitRecord::default();
+        if !self.hit(
+            &Ray::new(*origin, *direction),
+            0.001,
+            f32::MAX,
+            &mut rec,
+            sampler,
+        ) {
+            return 0.0;
         }
 
-        record.t = root;
-        record.p = ray.at(record.t);
-        let outward_normal = (record.p - self.center) / self.radius;
-        get_sphere_ui(&outward_normal, &mut record.u, &mut record.v);
-        record.set_face_normal(ray, outward_normal);
-        record.material = Some(self.material.clone());
+        let distance_squared = (self.center - *origin).length_squared();
+        let radius_squared = self.radius * self.radius;
 
-        true
+        // from the inside every direction reaches the sphere
+        if distance_squared <= radius_squared {
+            return 1.0 / (4.0 * PI);
+        }
+
+        let cos_theta_max = (1.0 - radius_squared / distance_squared).sqrt();
+        let solid_angle = 2.0 * PI * (1.0 - cos_theta_max);
+
+        1.0 / solid_angle
     }
 
-    fn bounding_box(&self, _time0: f32, _time1: f32, output_box: &mut AABB) -> bool {
-        *output_box = AABB::new(
-            self.center - Vec3::new(self.radius, self.radius, self.radius),
-            self.center + Vec3::new(self.radius, self.radius, self.radius),
-        );
+    fn random(&self, origin: &Point3, sampler: &mut dyn Sampler) -> Vec3 {
+        let direction = self.center - *origin;
+        let distance_squared = direction.length_squared();
+
+        if distance_squared <= self.radius * self.radius {
+            return random_unit_vector(sampler);
+        }
+
+        let uvw = ONB::build_from_w(direction);
+        uvw.local(random_to_sphere(sampler, self.radius, distance_squared))
+    }
+}
+
+/// A sphere moving in a straight line from `center0` at `time0` to `center1` at
+/// `time1`. It rests at the ends outside of that interval.
+pub struct MovingSphere {
+    pub center0: Point3,
+    pub center1: Point3,
+    pub time0: f32,
+    pub time1: f32,
+    pub radius: f32,
+    pub material: Arc<dyn Material>,
+}
+
+impl MovingSphere {
+    pub fn new(
+        center0: Point3,
+        center1: Point3,
+        time0: f32,
+        time1: f32,
+        radius: f32,
+        material: Arc<dyn Material>,
+    ) -> Arc<Self> {
+        Arc::new(MovingSphere {
+            center0,
+            center1,
+            time0,
+            time1,
+            radius,
+            material,
+        })
+    }
+
+    pub fn center(&self, time: f32) -> Point3 {
+        if self.time1 <= self.time0 {
+            return self.center0;
+        }
+
+        let t = ((time - self.time0) / (self.time1 - self.time0)).clamp(0.0, 1.0);
+        self.center0 + t * (self.center1 - self.center0)
+    }
+}
+
+impl Hittable for MovingSphere {
+    fn hit(
+        &self,
+        ray: &Ray,
+        t_min: f32,
+        t_max: f32,
+        record: &mut HitRecord,
+        _sampler: &mut dyn Sampler,
+    ) -> bool {
+        hit_sphere(
+            self.center(ray.time),
+            self.radius,
+            &self.material,
+            ray,
+            t_min,
+            t_max,
+            record,
+        )
+    }
+
+    fn bounding_box(&self, time0: f32, time1: f32, output_box: &mut AABB) -> bool {
+        // the path is a line, its ends bound everything in between
+        *output_box = sphere_box(self.center(time0), self.radius)
+            & sphere_box(self.center(time1), self.radius);
 
         true
     }
 }
 
+fn hit_sphere(
+    center: Point3,
+    radius: f32,
+    material: &Arc<dyn Material>,
+    ray: &Ray,
+    t_min: f32,
+    t_max: f32,
+    record: &mut HitRecord,
+) -> bool {
+    let oc = ray.orig - center;
+    let a = ray.dir.length_squared();
+    let half_b = oc.dot(ray.dir);
+    let c = oc.length_squared() - radius * radius;
+
+    let discriminant = half_b * half_b - a * c;
+    if discriminant < 0.0 {
+        return false;
+    };
+    let sqrtd = discriminant.sqrt();
+
+    let mut root = (-half_b - sqrtd) / a;
+    if root < t_min || t_max < root {
+        root = (-half_b + sqrtd) / a;
+        if root < t_min || t_max < root {
+            return false;
+        }
+    }
+
+    record.t = root;
+    record.p = ray.at(record.t);
+    let outward_normal = (record.p - center) / radius;
+    get_sphere_ui(&outward_normal, &mut record.u, &mut record.v);
+    record.set_face_normal(ray, outward_normal);
+    record.material = Some(material.clone());
+
+    true
+}
+
+fn sphere_box(center: Point3, radius: f32) -> AABB {
+    AABB::new(
+        center - Vec3::new(radius, radius, radius),
+        center + Vec3::new(radius, radius, radius),
+    )
+}
+
 pub fn get_sphere_ui(n: &Vec3, u: &mut f32, v: &mut f32) {
     let mut y = n.y;
 
@@ -1,8 +1,10 @@
 use std::sync::Arc;
 
-use image::{open as open_image, ImageBuffer, Rgb};
+use image::{open as open_image, ImageBuffer, ImageResult, Rgb};
+use serde::Deserialize;
 
 use super::helpers::*;
+use super::perlin::*;
 use super::vec2::*;
 use super::vec3::*;
 
@@ -64,11 +66,15 @@ impl Texture for DebugUVTexture {
     }
 }
 
+#[derive(Debug, Copy, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum TextureFiltering {
     Linear,
     Nearest,
 }
 
+#[derive(Debug, Copy, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum TextureFlip {
     AsIs,
     FlipY,
@@ -88,9 +94,26 @@ impl ImageTexture {
         flip: TextureFlip,
         repeating: Vec2,
     ) -> Arc<Self> {
-        let image = open_image(path).unwrap();
-        let image = image.as_rgb8().unwrap().clone();
+        Self::open(path, filtering, flip, repeating).unwrap()
+    }
+
+    pub fn open(
+        path: &str,
+        filtering: TextureFiltering,
+        flip: TextureFlip,
+        repeating: Vec2,
+    ) -> ImageResult<Arc<Self>> {
+        let image = open_image(path)?.to_rgb8();
+
+        Ok(Self::from_image(image, filtering, flip, repeating))
+    }
 
+    pub fn from_image(
+        image: ImageBuffer<Rgb<u8>, Vec<u8>>,
+        filtering: TextureFiltering,
+        flip: TextureFlip,
+        repeating: Vec2,
+    ) -> Arc<Self> {
         Arc::new(ImageTexture {
             image,
             filtering,
@@ -109,11 +132,11 @@ impl Texture for ImageTexture {
         let mut v = (v * self.repeating.y) % 1.0;
 
         if u < 0.0 {
-            u = u + 1.0;
+            u += 1.0;
         }
 
         if v < 0.0 {
-            v = v + 1.0;
+            v += 1.0;
         }
 
         v = match self.flip {
@@ -150,3 +173,164 @@ impl Texture for ImageTexture {
         }
     }
 }
+
+/// Perlin noise between the `low` and `high` colors.
+pub struct NoiseTexture {
+    pub noise: Perlin,
+    pub scale: f32,
+    pub low: Color,
+    pub high: Color,
+}
+
+impl NoiseTexture {
+    pub fn new(seed: u64, scale: f32, low: Color, high: Color) -> Arc<Self> {
+        Arc::new(NoiseTexture {
+            noise: Perlin::new(seed),
+            scale,
+            low,
+            high,
+        })
+    }
+}
+
+impl Texture for NoiseTexture {
+    fn value(&self, _u: f32, _v: f32, point: &Point3) -> Color {
+        let t = 0.5 * (1.0 + self.noise.noise(&(self.scale * *point)));
+        mix(self.low, self.high, t)
+    }
+}
+
+pub struct TurbulenceTexture {
+    pub noise: Perlin,
+    pub scale: f32,
+    pub depth: usize,
+    pub low: Color,
+    pub high: Color,
+}
+
+impl TurbulenceTexture {
+    pub fn new(seed: u64, scale: f32, depth: usize, low: Color, high: Color) -> Arc<Self> {
+        Arc::new(TurbulenceTexture {
+            noise: Perlin::new(seed),
+            scale,
+            depth,
+            low,
+            high,
+        })
+    }
+}
+
+impl Texture for TurbulenceTexture {
+    fn value(&self, _u: f32, _v: f32, point: &Point3) -> Color {
+        let t = self.noise.turbulence(&(self.scale * *point), self.depth);
+        mix(self.low, self.high, t)
+    }
+}
+
+pub struct FbmTexture {
+    pub noise: Perlin,
+    pub scale: f32,
+    pub octaves: usize,
+    /// Frequency multiplier between octaves.
+    pub lacunarity: f32,
+    /// Weight multiplier between octaves.
+    pub gain: f32,
+    pub low: Color,
+    pub high: Color,
+}
+
+impl FbmTexture {
+    pub fn new(
+        seed: u64,
+        scale: f32,
+        octaves: usize,
+        lacunarity: f32,
+        gain: f32,
+        low: Color,
+        high: Color,
+    ) -> Arc<Self> {
+        Arc::new(FbmTexture {
+            noise: Perlin::new(seed),
+            scale,
+            octaves,
+            lacunarity,
+            gain,
+            low,
+            high,
+        })
+    }
+}
+
+impl Texture for FbmTexture {
+    fn value(&self, _u: f32, _v: f32, point: &Point3) -> Color {
+        let noise = self.noise.fbm(
+            &(self.scale * *point),
+            self.octaves,
+            self.lacunarity,
+            self.gain,
+        );
+        mix(self.low, self.high, 0.5 * (1.0 + noise))
+    }
+}
+
+/// Veins along z, bent by turbulence.
+pub struct MarbleTexture {
+    pub noise: Perlin,
+    pub scale: f32,
+    pub depth: usize,
+    pub low: Color,
+    pub high: Color,
+}
+
+impl MarbleTexture {
+    pub fn new(seed: u64, scale: f32, depth: usize, low: Color, high: Color) -> Arc<Self> {
+        Arc::new(MarbleTexture {
+            noise: Perlin::new(seed),
+            scale,
+            depth,
+            low,
+            high,
+        })
+    }
+}
+
+impl Texture for MarbleTexture {
+    fn value(&self, _u: f32, _v: f32, point: &Point3) -> Color {
+        let phase = self.scale * point.z + 10.0 * self.noise.turbulence(point, self.depth);
+        mix(self.low, self.high, 0.5 * (1.0 + phase.sin()))
+    }
+}
+
+/// Growth rings around the y axis, `scale` of them per unit, wobbled by turbulence.
+pub struct WoodTexture {
+    pub noise: Perlin,
+    pub scale: f32,
+    pub depth: usize,
+    pub low: Color,
+    pub high: Color,
+}
+
+impl WoodTexture {
+    pub fn new(seed: u64, scale: f32, depth: usize, low: Color, high: Color) -> Arc<Self> {
+        Arc::new(WoodTexture {
+            noise: Perlin::new(seed),
+            scale,
+            depth,
+            low,
+            high,
+        })
+    }
+}
+
+impl Texture for WoodTexture {
+    fn value(&self, _u: f32, _v: f32, point: &Point3) -> Color {
+        let radius = (point.x * point.x + point.z * point.z).sqrt();
+        let rings = self.scale * radius + 2.0 * self.noise.turbulence(point, self.depth);
+        mix(self.low, self.high, rings - rings.floor())
+    }
+}
+
+fn mix(low: Color, high: Color, t: f32) -> Color {
+    let t = clamp(t, 0.0, 1.0);
+    low * (1.0 - t) + high * t
+}
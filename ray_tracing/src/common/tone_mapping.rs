@@ -0,0 +1,83 @@
+use serde::Deserialize;
+
+use super::vec3::*;
+
+/// Curve squeezing linear radiance into the displayable zero to one range.
+#[derive(Debug, Copy, Clone, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ToneMapping {
+    /// Cuts everything brighter than one.
+    #[default]
+    Clamp,
+    /// `L / (1 + L)` on the luminance, keeping hues.
+    Reinhard,
+    /// Narkowicz's fit of the ACES filmic curve, per channel.
+    Aces,
+}
+
+impl ToneMapping {
+    pub fn apply(&self, color: Color) -> Color {
+        match self {
+            ToneMapping::Clamp => color,
+            ToneMapping::Reinhard => {
+                let l = luminance(color);
+                if l <= 0.0 {
+                    return Color::zero();
+                }
+                color * (1.0 / (1.0 + l))
+            }
+            ToneMapping::Aces => Color::new(aces(color.x), aces(color.y), aces(color.z)),
+        }
+    }
+}
+
+/// How linear radiance turns into 8 or 16 bit pixels: scaled by the exposure,
+/// tone mapped and encoded with the sRGB curve.
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct DisplaySettings {
+    pub tone_mapping: ToneMapping,
+    /// In stops, every one doubles the brightness.
+    pub exposure: f32,
+}
+
+impl DisplaySettings {
+    /// sRGB encoded channels between zero and one.
+    pub fn encode(&self, color: &Color) -> Color {
+        let mapped = self.tone_mapping.apply(*color * 2f32.powf(self.exposure));
+
+        Color::new(
+            linear_to_srgb(mapped.x),
+            linear_to_srgb(mapped.y),
+            linear_to_srgb(mapped.z),
+        )
+    }
+}
+
+pub fn luminance(color: Color) -> f32 {
+    0.2126 * color.x + 0.7152 * color.y + 0.0722 * color.z
+}
+
+/// The sRGB transfer function, also clamping to zero to one. NaNs end up black.
+pub fn linear_to_srgb(v: f32) -> f32 {
+    let v = if v.is_nan() { 0.0 } else { v.clamp(0.0, 1.0) };
+
+    if v <= 0.003_130_8 {
+        12.92 * v
+    } else {
+        1.055 * v.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+pub fn srgb_to_linear(v: f32) -> f32 {
+    if v <= 0.040_45 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn aces(x: f32) -> f32 {
+    let (a, b, c, d, e) = (2.51, 0.03, 2.43, 0.59, 0.14);
+
+    (x * (a * x + b) / (x * (c * x + d) + e)).clamp(0.0, 1.0)
+}
@@ -5,6 +5,7 @@ use super::attribute::*;
 use super::hittable::*;
 use super::material::*;
 use super::ray::*;
+use super::sampler::*;
 use super::vec2::*;
 use super::vec3::*;
 
@@ -37,7 +38,14 @@ impl Triangle {
 }
 
 impl Hittable for Triangle {
-    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32, record: &mut HitRecord) -> bool {
+    fn hit(
+        &self,
+        ray: &Ray,
+        t_min: f32,
+        t_max: f32,
+        record: &mut HitRecord,
+        _sampler: &mut dyn Sampler,
+    ) -> bool {
         let Attribute { a, b, c } = self.position;
         let Attribute {
             a: na,
@@ -69,12 +77,12 @@ impl Hittable for Triangle {
         }
 
         let u = f * s.dot(x);
-        if u < 0.0 || u > 1.0 {
+        if !(0.0..=1.0).contains(&u) {
             return false;
         }
 
         let v = f * ray.dir.dot(y);
-        if v < 0.0 || v > 1.0 || u + v > 1.0 {
+        if !(0.0..=1.0).contains(&v) || u + v > 1.0 {
             return false;
         }
 
@@ -106,4 +114,42 @@ impl Hittable for Triangle {
 
         true
     }
+
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3, sampler: &mut dyn Sampler) -> f32 {
+        let mut rec = HitRecord::default();
+        if !self.hit(
+            &Ray::new(*origin, *direction),
+            0.001,
+            f32::MAX,
+            &mut rec,
+            sampler,
+        ) {
+            return 0.0;
+        }
+
+        let Attribute { a, b, c } = self.position;
+        let area = 0.5 * (b - a).cross(c - a).length();
+        let distance_squared = rec.t * rec.t * direction.length_squared();
+        let cosine = direction.dot(self.face_normal).abs() / direction.length();
+
+        // seen edge on the triangle covers no solid angle
+        if cosine < 1.0e-6 {
+            return 0.0;
+        }
+
+        distance_squared / (cosine * area)
+    }
+
+    fn random(&self, origin: &Point3, sampler: &mut dyn Sampler) -> Vec3 {
+        let Attribute { a, b, c } = self.position;
+        let Vec2 { x: mut u, y: mut v } = sampler.get_2d();
+
+        // fold the far half of the parallelogram back onto the triangle
+        if u + v > 1.0 {
+            u = 1.0 - u;
+            v = 1.0 - v;
+        }
+
+        a + u * (b - a) + v * (c - a) - *origin
+    }
 }
@@ -1,9 +1,10 @@
 use std::ops;
 
+use nalgebra as na;
 use partial_min_max::{max, min};
-use rand::prelude::*;
 
 use super::helpers::clamp;
+use super::sampler::*;
 
 #[derive(Debug, Copy, Clone, Default)]
 pub struct Vec3 {
@@ -32,16 +33,14 @@ impl Vec3 {
         Self { x, y, z }
     }
 
-    pub fn random() -> Self {
-        let mut rnd = rand::thread_rng();
-        Self::new(rnd.gen(), rnd.gen(), rnd.gen())
+    pub fn random(sampler: &mut dyn Sampler) -> Self {
+        Self::new(sampler.get_1d(), sampler.get_1d(), sampler.get_1d())
     }
-    pub fn random_range(from: f32, to: f32) -> Self {
-        let mut rnd = rand::thread_rng();
+    pub fn random_range(sampler: &mut dyn Sampler, from: f32, to: f32) -> Self {
         Self::new(
-            rnd.gen_range(from..to),
-            rnd.gen_range(from..to),
-            rnd.gen_range(from..to),
+            from + (to - from) * sampler.get_1d(),
+            from + (to - from) * sampler.get_1d(),
+            from + (to - from) * sampler.get_1d(),
         )
     }
 
@@ -58,7 +57,7 @@ impl Vec3 {
         let cos_theta = clamp((-self).dot(n), -1.0, 1.0);
         let r_out_perp = etai_over_etat * (self + cos_theta * n);
         let r_out_parallel = -(1.0 - r_out_perp.length_squared()).abs().sqrt() * n;
-        return r_out_perp + r_out_parallel;
+        r_out_perp + r_out_parallel
     }
 
     pub fn min(self, other: Vec3) -> Vec3 {
@@ -78,6 +77,30 @@ impl Vec3 {
     }
 }
 
+impl From<na::Vector3<f32>> for Vec3 {
+    fn from(v: na::Vector3<f32>) -> Self {
+        Self::new(v.x, v.y, v.z)
+    }
+}
+
+impl From<na::Point3<f32>> for Vec3 {
+    fn from(p: na::Point3<f32>) -> Self {
+        Self::new(p.x, p.y, p.z)
+    }
+}
+
+impl From<Vec3> for na::Vector3<f32> {
+    fn from(v: Vec3) -> Self {
+        Self::new(v.x, v.y, v.z)
+    }
+}
+
+impl From<Vec3> for na::Point3<f32> {
+    fn from(p: Vec3) -> Self {
+        Self::new(p.x, p.y, p.z)
+    }
+}
+
 impl ops::Neg for Vec3 {
     type Output = Self;
 
@@ -156,6 +179,14 @@ impl ops::Div<f32> for Vec3 {
     }
 }
 
+impl ops::Div for Vec3 {
+    type Output = Self;
+
+    fn div(self, other: Vec3) -> Self::Output {
+        Self::new(self.x / other.x, self.y / other.y, self.z / other.z)
+    }
+}
+
 impl ops::Index<usize> for Vec3 {
     type Output = f32;
 
@@ -1,10 +1,35 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 use std::time::Instant;
 
-use image::{ImageBuffer, Rgb};
 use rand::prelude::*;
 use rayon::prelude::*;
+use serde::Deserialize;
 
 use crate::common::*;
+use crate::progress::*;
+
+/// Bounces that are always followed before Russian roulette kicks in, few enough
+/// to keep deep paths cheap but past the ones that carry most of the light. The
+/// maximum depth only stops the rare paths that keep surviving, and should be
+/// well above it.
+pub const DEFAULT_ROULETTE_DEPTH: i32 = 5;
+
+/// How the renderer estimates the light arriving along a camera ray.
+#[derive(Debug, Copy, Clone, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Integrator {
+    /// `ray_color`, every bounce samples a mix of the material and the lights.
+    Mixture,
+    /// `ray_color_nee`, a shadow ray to the lights at every bounce besides the material
+    /// bounce, the two weighted with multiple importance sampling.
+    #[default]
+    Nee,
+}
 
 pub struct CPURenderingParams<T: Hittable> {
     pub world: T,
@@ -12,93 +37,1096 @@ pub struct CPURenderingParams<T: Hittable> {
     pub image_width: u32,
     pub samples_per_pixel: u32,
     pub max_depth: i32,
+    /// Bounces after which paths start ending at random.
+    pub roulette_depth: i32,
     pub aspect_ratio: f32,
     pub path: String,
-    pub background: Color,
+    /// Format of the saved image, guessed from the extension of `path` when unset.
+    pub format: Option<OutputFormat>,
+    pub display: DisplaySettings,
+    pub background: Background,
+    /// Objects sampled directly as lights, alongside the material bounces.
+    pub lights: HittableList,
+    pub integrator: Integrator,
+    /// How the samples of every pixel are spread over the pixel, lens and bounces.
+    pub sampler: SamplerKind,
+    /// Spreads every sample over the pixels around it, the box filter keeps it in
+    /// its own pixel.
+    pub filter: Filter,
+    /// Renders in passes with intermediate images instead of all samples at once.
+    pub progressive: Option<ProgressiveSettings>,
+    /// Stops sampling pixels that are clean enough, `samples_per_pixel` becomes
+    /// the most any pixel takes.
+    pub adaptive: Option<AdaptiveSettings>,
+    /// Extra images written next to the render.
+    pub aovs: Vec<Aov>,
+    /// Filters the noise out of the finished image, which is also saved as it was
+    /// next to it, like `cornell.noisy.png`.
+    pub denoise: Option<DenoiseSettings>,
+    /// Side of the square tiles the image is rendered in.
+    pub tile_size: u32,
+    /// Told about finished tiles and passes, and asked whether to stop.
+    pub observer: Option<Arc<dyn RenderObserver>>,
+    /// Seeds every random choice of the render, so a seed always gives the same
+    /// image whatever the number of threads. A random one is picked when none is given.
+    pub seed: Option<u64>,
+}
+
+/// Samples are added to the image in passes, and every so often the image so far is
+/// written together with a checkpoint that a killed render can be resumed from.
+#[derive(Debug, Clone)]
+pub struct ProgressiveSettings {
+    /// Samples added to every pixel by each pass.
+    pub pass_samples: u32,
+    /// Seconds between writes of the image and the checkpoint. They are also
+    /// written once the render is done.
+    pub interval: f32,
+    /// Defaults to the image path with `.checkpoint` appended.
+    pub checkpoint: Option<String>,
+    /// Carries on from the checkpoint if there is one. Resuming a finished render
+    /// with more samples per pixel refines it further, except with the stratified
+    /// sampler.
+    pub resume: bool,
+    /// Tells the checkpoint which scene it is of, and which version of it, so
+    /// a checkpoint of another one isn't resumed.
+    pub scene: String,
+}
+
+impl Default for ProgressiveSettings {
+    fn default() -> Self {
+        Self {
+            pass_samples: 16,
+            interval: 60.0,
+            checkpoint: None,
+            resume: false,
+            scene: String::new(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum RenderError {
+    Output(OutputError),
+    Checkpoint(CheckpointError),
+    /// The observer cancelled the render after `samples` samples per pixel. Unless
+    /// no pass finished, what they add up to has been saved, without denoising.
+    Cancelled {
+        samples: u32,
+    },
+}
+
+impl fmt::Display for RenderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RenderError::Output(e) => write!(f, "{}", e),
+            RenderError::Checkpoint(e) => write!(f, "{}", e),
+            RenderError::Cancelled { samples } => {
+                write!(f, "cancelled at {} samples per pixel", samples)
+            }
+        }
+    }
+}
+
+impl Error for RenderError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            RenderError::Output(e) => Some(e),
+            RenderError::Checkpoint(e) => Some(e),
+            RenderError::Cancelled { .. } => None,
+        }
+    }
+}
+
+impl From<OutputError> for RenderError {
+    fn from(e: OutputError) -> Self {
+        RenderError::Output(e)
+    }
 }
 
-pub fn render_world_cpu<T: Hittable>(params: CPURenderingParams<T>) {
+impl From<CheckpointError> for RenderError {
+    fn from(e: CheckpointError) -> Self {
+        RenderError::Checkpoint(e)
+    }
+}
+
+/// Renders into a linear framebuffer and saves it to `params.path`.
+pub fn render_world_cpu<T: Hittable>(
+    params: CPURenderingParams<T>,
+) -> Result<Framebuffer, RenderError> {
     let CPURenderingParams {
         world,
         camera,
         image_width,
         samples_per_pixel,
         max_depth,
+        roulette_depth,
         aspect_ratio,
         path,
+        format,
+        display,
         background,
+        lights,
+        integrator,
+        sampler: sampler_kind,
+        filter,
+        progressive,
+        adaptive,
+        aovs,
+        denoise: denoise_settings,
+        tile_size,
+        observer,
+        seed,
     } = params;
 
     let image_height = (image_width as f32 / aspect_ratio) as u32;
+    let format = format.unwrap_or_else(|| OutputFormat::from_path(&path));
+    let checkpoint_path = progressive.as_ref().map(|progressive| {
+        progressive
+            .checkpoint
+            .clone()
+            .unwrap_or_else(|| format!("{}.checkpoint", path))
+    });
+
+    // stratified samples are laid out for the samples per pixel, more of them
+    // would fall in the strata the previous ones took
+    let strata = match sampler_kind {
+        SamplerKind::Stratified => samples_per_pixel,
+        _ => 0,
+    };
+    let settings = settings_hash(
+        format!(
+            "{}|{:?}|{:?}|{:?}|{}|{}|{}",
+            progressive
+                .as_ref()
+                .map_or("", |progressive| progressive.scene.as_str()),
+            sampler_kind,
+            filter,
+            integrator,
+            max_depth,
+            roulette_depth,
+            strata
+        )
+        .as_bytes(),
+    );
+    let mut resumed = None;
+
+    if let (Some(progressive), Some(checkpoint_path)) = (&progressive, &checkpoint_path) {
+        if progressive.resume && Path::new(checkpoint_path).exists() {
+            let checkpoint = Checkpoint::load(checkpoint_path)?;
+            let mismatch = |message: String| -> RenderError {
+                CheckpointError::Invalid {
+                    path: checkpoint_path.into(),
+                    message,
+                }
+                .into()
+            };
+
+            if checkpoint.width != image_width || checkpoint.height != image_height {
+                return Err(mismatch(format!(
+                    "checkpoint is {}x{} but the image is {}x{}",
+                    checkpoint.width, checkpoint.height, image_width, image_height
+                )));
+            }
+
+            if checkpoint.settings != settings {
+                return Err(mismatch(String::from(
+                    "checkpoint is of another scene or was rendered with other settings",
+                )));
+            }
+
+            // a render without a seed carries on with the one it started with
+            if let Some(seed) = seed.filter(|seed| *seed != checkpoint.seed) {
+                return Err(mismatch(format!(
+                    "checkpoint has seed {} but the render has seed {}",
+                    checkpoint.seed, seed
+                )));
+            }
+
+            println!("resuming from {} samples", checkpoint.samples);
+            resumed = Some(checkpoint);
+        }
+    }
+
+    let seed = match &resumed {
+        Some(checkpoint) => checkpoint.seed,
+        None => seed.unwrap_or_else(|| rand::thread_rng().gen()),
+    };
+    let mut state =
+        resumed.unwrap_or_else(|| Checkpoint::new(image_width, image_height, seed, settings));
+
+    let pass_samples = match (&progressive, &adaptive) {
+        (Some(progressive), _) => progressive.pass_samples.max(1),
+        (None, Some(adaptive)) => adaptive.min_samples.max(1),
+        (None, None) => samples_per_pixel.max(1),
+    };
+
+    let tiles = Tile::split(image_width, image_height, tile_size);
+    // first hit features are recorded from the first samples of every pixel
+    let wants_features = denoise_settings.is_some() || aovs.iter().any(|aov| !aov.is_light());
+    let mut feature_sums = if wants_features {
+        vec![FeatureSum::default(); state.pixels.len()]
+    } else {
+        vec![]
+    };
+    let start_samples = state.samples;
+    let run_samples = samples_per_pixel.saturating_sub(start_samples);
+    let pass_count = run_samples.div_ceil(pass_samples);
+    let mut cancelled = false;
+
+    // the first dimensions of every sample place it in the pixel and on the lens,
+    // the filter spreads it around from where it is in the pixel
+    let camera_ray = |x: u32, y: u32, sampler: &mut dyn Sampler| {
+        let offset = sampler.get_pixel_2d();
+        let u = (x as f32 + offset.x) / (image_width - 1) as f32;
+        let v = 1.0 - (y as f32 + offset.y) / (image_height - 1) as f32;
+        (offset, camera.get_ray(u, v, sampler))
+    };
+    let new_sampler = || sampler_kind.create(samples_per_pixel, seed);
 
     let now = Instant::now();
+    let mut last_write = Instant::now();
 
     println!("begin rendering...");
 
-    let pixels = (0..image_height * image_width)
-        .into_par_iter()
-        .map(|i| {
-            let x = i % image_width;
-            let y = (i - x) / image_width;
-            let mut rnd = rand::thread_rng();
-            let mut pixel_color = Color::default();
+    let pixels_to_sample = |state: &Checkpoint| match &adaptive {
+        Some(adaptive) if state.samples >= adaptive.min_samples => {
+            active_pixels(state, adaptive.threshold, samples_per_pixel)
+        }
+        _ => vec![true; state.pixels.len()],
+    };
+    let mut active = pixels_to_sample(&state);
+
+    for pass in 0..pass_count {
+        let samples = pass_samples.min(samples_per_pixel - state.samples);
+        let first_sample = state.samples;
+
+        // only a resumed render can have converged before its first pass
+        if !active.contains(&true) {
+            println!("every pixel converged at {} samples", first_sample);
+            break;
+        }
+
+        let tiles_done = AtomicUsize::new(0);
+        let progress = |tiles_done: usize| Progress {
+            pass,
+            pass_count,
+            tiles_done,
+            tile_count: tiles.len(),
+            samples: first_sample + samples,
+            samples_per_pixel,
+            elapsed: now.elapsed(),
+            done: ((first_sample - start_samples) as f32 * tiles.len() as f32
+                + tiles_done as f32 * samples as f32)
+                / (run_samples as f32 * tiles.len() as f32),
+        };
+        let previous = &state.pixels;
+        let previous_features = &feature_sums;
+
+        let results = tiles
+            .par_iter()
+            .map(|tile| {
+                if observer.as_ref().is_some_and(|o| o.cancelled()) {
+                    return None;
+                }
+
+                let tile_start = Instant::now();
+                let mut sampler = new_sampler();
+                // samples near the edges of the tile also land in the tiles around it
+                let area = tile.grown(filter.reach(), image_width, image_height);
+                let at = |x: u32, y: u32| ((y - area.y) * area.width + x - area.x) as usize;
+                let mut added = vec![PixelSamples::default(); area.pixel_count()];
+                let mut features = if wants_features {
+                    vec![FeatureSum::default(); tile.pixel_count()]
+                } else {
+                    vec![]
+                };
+
+                for y in tile.y..tile.y + tile.height {
+                    for x in tile.x..tile.x + tile.width {
+                        let i = (y * image_width + x) as usize;
+
+                        if !active[i] {
+                            continue;
+                        }
+
+                        // samples are numbered across passes, so a resumed render
+                        // samples like an uninterrupted one
+                        for index in previous[i].count..previous[i].count + samples {
+                            sampler.start_pixel_sample(x, y, index);
+                            let (offset, ray) = camera_ray(x, y, sampler.as_mut());
+                            let radiance = match integrator {
+                                Integrator::Mixture => ray_color(
+                                    &ray,
+                                    &background,
+                                    &world,
+                                    &lights,
+                                    max_depth,
+                                    roulette_depth,
+                                    sampler.as_mut(),
+                                ),
+                                Integrator::Nee => ray_color_nee(
+                                    &ray,
+                                    &background,
+                                    &world,
+                                    &lights,
+                                    max_depth,
+                                    roulette_depth,
+                                    sampler.as_mut(),
+                                ),
+                            };
+                            let (color, direct) = (radiance.total(), radiance.direct);
+
+                            if wants_features {
+                                let j = ((y - tile.y) * tile.width + x - tile.x) as usize;
+                                if previous_features[i].count + features[j].count < FEATURE_SAMPLES
+                                {
+                                    features[j].add(radiance.first_hit);
+                                }
+                            }
+
+                            added[at(x, y)].add(color, direct);
+                            for (py, dy) in filter.footprint(y, offset.y, image_height) {
+                                for (px, dx) in filter.footprint(x, offset.x, image_width) {
+                                    added[at(px, py)].splat(color, direct, filter.weight(dx, dy));
+                                }
+                            }
+                        }
+                    }
+                }
+
+                if let Some(observer) = &observer {
+                    let done = tiles_done.fetch_add(1, Ordering::Relaxed) + 1;
+                    // without what the tiles around it add to its edges in this pass
+                    let averages: Vec<Color> = (tile.y..tile.y + tile.height)
+                        .flat_map(|y| (tile.x..tile.x + tile.width).map(move |x| (x, y)))
+                        .map(|(x, y)| {
+                            let mut pixel = previous[(y * image_width + x) as usize];
+                            pixel.merge(&added[at(x, y)]);
+                            pixel.mean()
+                        })
+                        .collect();
+                    observer.tile_done(&progress(done), tile, &averages, tile_start.elapsed());
+                }
+
+                Some((*tile, area, added, features))
+            })
+            .collect::<Vec<_>>();
 
-            for _ in 0..samples_per_pixel {
-                let u = (x as f32 + rnd.gen::<f32>()) / (image_width - 1) as f32;
-                let vv = (y as f32 + rnd.gen::<f32>()) / (image_height - 1) as f32;
-                let v = 1.0 - vv;
-                let ray = camera.get_ray(u, v);
-                pixel_color += ray_color(&ray, &background, &world, max_depth);
+        // an unfinished pass would leave pixels with different sample counts
+        if results.iter().any(Option::is_none) {
+            cancelled = true;
+            break;
+        }
+
+        // in the order of the tiles, so the sums don't depend on the threads
+        for (tile, area, added, features) in results.into_iter().flatten() {
+            for (row, chunk) in added.chunks_exact(area.width as usize).enumerate() {
+                let start = ((area.y + row as u32) * image_width + area.x) as usize;
+                for (pixel, added) in state.pixels[start..start + chunk.len()]
+                    .iter_mut()
+                    .zip(chunk)
+                {
+                    pixel.merge(added);
+                }
             }
 
-            to_rgb(&pixel_color, samples_per_pixel)
-        })
-        .flatten()
-        .collect::<Vec<u8>>();
+            for (row, chunk) in features.chunks_exact(tile.width as usize).enumerate() {
+                let start = ((tile.y + row as u32) * image_width + tile.x) as usize;
+                for (sum, added) in feature_sums[start..start + chunk.len()]
+                    .iter_mut()
+                    .zip(chunk)
+                {
+                    sum.merge(added);
+                }
+            }
+        }
+
+        state.samples += samples;
+        active = pixels_to_sample(&state);
+        // a pass after which every pixel converged is the last one
+        let converged = state.samples < samples_per_pixel && !active.contains(&true);
+
+        if let Some(observer) = &observer {
+            let mut progress = progress(tiles.len());
+            if converged {
+                progress.pass_count = pass + 1;
+                progress.done = 1.0;
+            }
+            observer.pass_done(&progress);
+        }
+
+        if converged {
+            println!("every pixel converged at {} samples", state.samples);
+            break;
+        }
+
+        if let (Some(progressive), Some(checkpoint_path)) = (&progressive, &checkpoint_path) {
+            if state.samples < samples_per_pixel
+                && last_write.elapsed().as_secs_f32() >= progressive.interval
+            {
+                println!("saving -> {}", path);
+                state.framebuffer().save(&path, format, &display)?;
+                state.save(checkpoint_path)?;
+                last_write = Instant::now();
+            }
+        }
+    }
+
+    if cancelled {
+        println!("cancelled at {} samples", state.samples);
 
-    let img: ImageBuffer<Rgb<u8>, Vec<u8>> =
-        ImageBuffer::from_vec(image_width, image_height, pixels).unwrap();
+        // whatever was saved before is better than a black image
+        if state.samples == start_samples {
+            return Err(RenderError::Cancelled {
+                samples: state.samples,
+            });
+        }
+    }
+
+    let mut framebuffer = state.framebuffer();
 
     println!(
         "rendered for {} s",
         now.elapsed().as_millis() as f32 / 1000.0
     );
 
+    // a cancelled render is saved as it is, as quickly as possible
+    let features = if wants_features && !cancelled {
+        Some(collect_features(
+            &world,
+            &background,
+            &tiles,
+            (image_width, image_height),
+            &new_sampler,
+            &|x, y, sampler: &mut dyn Sampler| camera_ray(x, y, sampler).1,
+            (&feature_sums, FEATURE_SAMPLES.min(samples_per_pixel)),
+        ))
+    } else {
+        None
+    };
+
+    if let (Some(settings), Some(features)) = (&denoise_settings, &features) {
+        let noisy_path = companion_path(&path, "noisy");
+        println!("saving -> {}", noisy_path);
+        framebuffer.save(&noisy_path, format, &display)?;
+
+        let guides = DenoiseGuides {
+            albedo: Some(features.framebuffer(Aov::Albedo)),
+            normal: Some(features.framebuffer(Aov::Normal)),
+            depth: Some(features.framebuffer(Aov::Depth)),
+            // the noise of a pixel can be told from its samples once it has two
+            variance: if state.pixels.iter().all(|pixel| pixel.count >= 2) {
+                Some(
+                    state
+                        .pixels
+                        .iter()
+                        .map(|pixel| pixel.standard_error().powi(2))
+                        .collect(),
+                )
+            } else {
+                None
+            },
+        };
+
+        let denoise_start = Instant::now();
+        framebuffer = denoise(&framebuffer, &guides, settings);
+        println!(
+            "denoised in {} s",
+            denoise_start.elapsed().as_millis() as f32 / 1000.0
+        );
+    }
+
     println!("saving -> {}", path);
 
-    img.save(path).unwrap();
+    framebuffer.save(&path, format, &display)?;
+
+    if let Some(adaptive) = &adaptive {
+        let taken: u64 = state.pixels.iter().map(|p| p.count as u64).sum();
+        let budget = samples_per_pixel as u64 * state.pixels.len() as u64;
+        println!(
+            "adaptive sampling took {:.1}% of the samples",
+            100.0 * taken as f32 / budget.max(1) as f32
+        );
+
+        let heatmap = adaptive
+            .heatmap
+            .clone()
+            .unwrap_or_else(|| heatmap_path(&path));
+        println!("saving -> {}", heatmap);
+        sample_heatmap(&state, samples_per_pixel).save(
+            &heatmap,
+            OutputFormat::Ldr,
+            &DisplaySettings::default(),
+        )?;
+    }
+
+    if let Some(features) = &features {
+        for aov in aovs.iter().filter(|aov| !aov.is_light()) {
+            let aov_path = aov.path(&path);
+            println!("saving -> {}", aov_path);
+
+            match format {
+                OutputFormat::Exr | OutputFormat::Hdr => features
+                    .framebuffer(*aov)
+                    .save(&aov_path, format, &display)?,
+                OutputFormat::Ldr | OutputFormat::Png16 => {
+                    features
+                        .preview(*aov)
+                        .save(&aov_path, format, &DisplaySettings::default())?;
+
+                    // the preview can't be turned back into depths, which the
+                    // denoiser wants for guidance
+                    if *aov == Aov::Depth {
+                        let raw_path = Path::new(&aov_path).with_extension("exr");
+                        println!("saving -> {}", raw_path.display());
+                        features.framebuffer(*aov).save(
+                            &raw_path,
+                            OutputFormat::Exr,
+                            &DisplaySettings::default(),
+                        )?;
+                    }
+                }
+            }
+        }
+    }
+
+    for aov in aovs.iter().filter(|aov| aov.is_light()) {
+        let mean = match aov {
+            Aov::Direct => PixelSamples::direct_mean,
+            _ => PixelSamples::indirect_mean,
+        };
+        let aov_path = aov.path(&path);
+        println!("saving -> {}", aov_path);
+
+        Framebuffer::from_pixels(
+            image_width,
+            image_height,
+            state.pixels.iter().map(mean).collect(),
+        )
+        .save(&aov_path, format, &display)?;
+    }
+
+    if let Some(checkpoint_path) = &checkpoint_path {
+        state.save(checkpoint_path)?;
+    }
+
+    if cancelled {
+        return Err(RenderError::Cancelled {
+            samples: state.samples,
+        });
+    }
+
+    Ok(framebuffer)
 }
 
-pub fn ray_color<T: Hittable>(ray: &Ray, background: &Color, world: &T, depth: i32) -> Color {
+/// First hit features of a pixel added up over its camera rays.
+#[derive(Debug, Copy, Clone, Default)]
+struct FeatureSum {
+    sum: PixelFeatures,
+    count: u32,
+    /// Rays that hit something, the depth is averaged over them alone.
+    hits: u32,
+    /// Material of the first ray, IDs can't be averaged.
+    material: usize,
+}
+
+impl FeatureSum {
+    fn add(&mut self, (features, material): (PixelFeatures, usize)) {
+        self.merge(&FeatureSum {
+            sum: features,
+            count: 1,
+            hits: (features.depth > 0.0) as u32,
+            material,
+        });
+    }
+
+    fn merge(&mut self, other: &FeatureSum) {
+        if self.count == 0 {
+            self.material = other.material;
+            self.sum.object_id = other.sum.object_id;
+        }
+
+        self.sum.albedo += other.sum.albedo;
+        self.sum.normal += other.sum.normal;
+        self.sum.depth += other.sum.depth;
+        self.sum.uv = self.sum.uv + other.sum.uv;
+        self.count += other.count;
+        self.hits += other.hits;
+    }
+
+    fn mean(&self) -> (PixelFeatures, usize) {
+        let weight = 1.0 / self.count.max(1) as f32;
+        let features = PixelFeatures {
+            albedo: self.sum.albedo * weight,
+            normal: self.sum.normal * weight,
+            // misses would pull the depth of edges towards the camera
+            depth: self.sum.depth / self.hits.max(1) as f32,
+            uv: self.sum.uv * weight,
+            material_id: 0,
+            object_id: self.sum.object_id,
+        };
+
+        (features, self.material)
+    }
+}
+
+/// Features of every pixel from the sums the render recorded. Pixels that took no
+/// samples, like all of them when a finished render is resumed, trace `samples`
+/// camera rays of their own.
+fn collect_features<T: Hittable>(
+    world: &T,
+    background: &Background,
+    tiles: &[Tile],
+    (width, height): (u32, u32),
+    new_sampler: &(dyn Fn() -> Box<dyn Sampler> + Sync),
+    camera_ray: &(dyn Fn(u32, u32, &mut dyn Sampler) -> Ray + Sync),
+    (sums, samples): (&[FeatureSum], u32),
+) -> Features {
+    let results = tiles
+        .par_iter()
+        .map(|tile| {
+            let mut sampler = new_sampler();
+            let mut pixels = Vec::with_capacity(tile.pixel_count());
+
+            for y in tile.y..tile.y + tile.height {
+                for x in tile.x..tile.x + tile.width {
+                    let mut sum = sums[(y * width + x) as usize];
+
+                    if sum.count == 0 {
+                        for index in 0..samples {
+                            sampler.start_pixel_sample(x, y, index);
+                            let ray = camera_ray(x, y, sampler.as_mut());
+                            sum.add(first_hit(&ray, background, world, sampler.as_mut()));
+                        }
+                    }
+
+                    pixels.push(sum.mean());
+                }
+            }
+
+            (*tile, pixels)
+        })
+        .collect::<Vec<_>>();
+
+    let mut pixels = vec![(PixelFeatures::default(), 0); (width * height) as usize];
+    for (tile, tile_pixels) in results {
+        for (row, chunk) in tile_pixels.chunks_exact(tile.width as usize).enumerate() {
+            let start = ((tile.y + row as u32) * width + tile.x) as usize;
+            pixels[start..start + chunk.len()].copy_from_slice(chunk);
+        }
+    }
+
+    // materials are numbered in the order they show up, which doesn't depend on the
+    // threads like the addresses identifying them do
+    let mut material_ids = HashMap::new();
+    let pixels = pixels
+        .into_iter()
+        .map(|(features, material)| {
+            let next_id = material_ids.len() as u32 + 1;
+            PixelFeatures {
+                material_id: match material {
+                    0 => 0,
+                    _ => *material_ids.entry(material).or_insert(next_id),
+                },
+                ..features
+            }
+        })
+        .collect();
+
+    Features {
+        width,
+        height,
+        pixels,
+    }
+}
+
+/// Features of the first surface `ray` hits, like the integrators record them.
+fn first_hit<T: Hittable>(
+    ray: &Ray,
+    background: &Background,
+    world: &T,
+    sampler: &mut dyn Sampler,
+) -> (PixelFeatures, usize) {
     let mut rec = HitRecord::default();
 
-    if depth <= 0 {
-        return Color::new(0.0, 0.0, 0.0);
+    if !world.hit(ray, 0.001, f32::MAX, &mut rec, sampler) || rec.material.is_none() {
+        return miss_features(ray, background);
+    }
+
+    if let Some(override_color) = rec.override_color {
+        return hit_features(ray, &rec, override_color);
     }
 
-    if world.hit(ray, 0.001, f32::MAX, &mut rec) && rec.material.is_some() {
+    let material = rec.material.clone().unwrap();
+    let mut srec = ScatterRecord::default();
+    material.scatter(ray, &rec, &mut srec, sampler);
+
+    surface_features(ray, &rec, material.emitted(&rec), &srec)
+}
+
+/// Features of a surface that gives off `emitted` and scattered `srec`: lights
+/// have their light as albedo, the others their color.
+fn surface_features(
+    ray: &Ray,
+    rec: &HitRecord,
+    emitted: Color,
+    srec: &ScatterRecord,
+) -> (PixelFeatures, usize) {
+    // metal sets its color even for bounces that end up under the surface
+    let albedo = if emitted.near_zero() {
+        srec.attenuation
+    } else {
+        emitted
+    };
+
+    hit_features(ray, rec, albedo)
+}
+
+/// Features of the surface `rec` with the address of its material. Albedo is
+/// clamped to one, so lights show up white.
+fn hit_features(ray: &Ray, rec: &HitRecord, albedo: Color) -> (PixelFeatures, usize) {
+    let features = PixelFeatures {
+        albedo: clamped(albedo),
+        normal: rec.normal,
+        depth: (rec.p - ray.orig).length(),
+        uv: Vec2::new(rec.u, rec.v),
+        material_id: 0,
+        object_id: rec.object_id,
+    };
+    let material = rec
+        .material
+        .as_ref()
+        .map_or(0, |material| Arc::as_ptr(material) as *const () as usize);
+
+    (features, material)
+}
+
+/// Features where nothing is hit, the background standing in for the albedo.
+fn miss_features(ray: &Ray, background: &Background) -> (PixelFeatures, usize) {
+    let features = PixelFeatures {
+        albedo: clamped(background.value(ray)),
+        ..PixelFeatures::default()
+    };
+
+    (features, 0)
+}
+
+fn clamped(c: Color) -> Color {
+    Color::new(c.x.min(1.0), c.y.min(1.0), c.z.min(1.0))
+}
+
+/// `cornell.png` gets its sample counts in `cornell.samples.png`.
+fn heatmap_path(path: &str) -> String {
+    Path::new(path)
+        .with_extension("samples.png")
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Light arriving along a camera ray, split by the surfaces it bounced off.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Radiance {
+    /// Seen directly or after bouncing off the first surface hit.
+    pub direct: Color,
+    /// Bounced off two surfaces or more.
+    pub indirect: Color,
+    /// Features of what the ray hit first, with the address of its material to
+    /// tell materials apart, zero where nothing was hit.
+    pub first_hit: (PixelFeatures, usize),
+}
+
+impl Radiance {
+    /// Light that reached the camera after bouncing off `bounces` surfaces.
+    pub fn new(bounces: i32, color: Color) -> Self {
+        let mut radiance = Self::default();
+        radiance.add(bounces, color);
+        radiance
+    }
+
+    pub fn total(&self) -> Color {
+        self.direct + self.indirect
+    }
+
+    pub fn add(&mut self, bounces: i32, color: Color) {
+        if bounces <= 1 {
+            self.direct += color;
+        } else {
+            self.indirect += color;
+        }
+    }
+}
+
+/// Path tracer sampling every diffuse bounce from a mix of the material and the
+/// lights. Bounces are followed in a loop and ended with Russian roulette like in
+/// `ray_color_nee`.
+pub fn ray_color<T: Hittable>(
+    ray: &Ray,
+    background: &Background,
+    world: &T,
+    lights: &HittableList,
+    depth: i32,
+    roulette_depth: i32,
+    sampler: &mut dyn Sampler,
+) -> Radiance {
+    let mut radiance = Radiance::default();
+    let mut throughput = Color::new(1.0, 1.0, 1.0);
+    let mut ray = *ray;
+
+    for bounce in 0..depth {
+        let mut rec = HitRecord::default();
+
+        if !world.hit(&ray, 0.001, f32::MAX, &mut rec, sampler) || rec.material.is_none() {
+            if bounce == 0 {
+                radiance.first_hit = miss_features(&ray, background);
+            }
+            radiance.add(bounce, throughput * background.value(&ray));
+            break;
+        }
+
         if let Some(override_color) = rec.override_color {
-            return override_color;
+            if bounce == 0 {
+                radiance.first_hit = hit_features(&ray, &rec, override_color);
+            }
+            radiance.add(bounce, throughput * override_color);
+            break;
         }
 
         let material = rec.material.clone().unwrap();
-        let mut scattered = Ray::default();
-        let mut albedo = Color::default();
+        let mut srec = ScatterRecord::default();
         let emitted = material.emitted(&rec);
-        let mut pdf = 0.0;
+        radiance.add(bounce, throughput * emitted);
+
+        let scatters = material.scatter(&ray, &rec, &mut srec, sampler);
+        if bounce == 0 {
+            radiance.first_hit = surface_features(&ray, &rec, emitted, &srec);
+        }
+        if !scatters {
+            break;
+        }
+
+        // specular materials don't report a pdf, their ray is followed as is
+        if let Some(specular_ray) = srec.specular_ray {
+            throughput = throughput * srec.attenuation;
+            ray = specular_ray;
+        } else {
+            let material_pdf = match &srec.pdf {
+                Some(pdf) => pdf.as_ref(),
+                None => break,
+            };
+
+            // half of the bounces go straight for the lights
+            let light_pdf = HittablePdf::new(lights, rec.p);
+            let mixture_pdf = MixturePdf::new(&light_pdf, material_pdf);
+            let pdf: &dyn Pdf = if lights.is_empty() {
+                material_pdf
+            } else {
+                &mixture_pdf
+            };
+
+            let mut scattered =
+                Ray::with_time(rec.p, pdf.generate(sampler).unit_vector(), ray.time);
+            let pdf_value = pdf.value(&scattered.dir, sampler);
 
-        if !material.scatter(&ray, &rec, &mut albedo, &mut scattered, &mut pdf) {
-            return emitted;
+            if pdf_value <= 0.0 {
+                break;
+            }
+
+            throughput =
+                throughput * srec.attenuation * material.scattering_pdf(&ray, &rec, &mut scattered)
+                    / pdf_value;
+            ray = scattered;
         }
 
-        return emitted
-            + albedo
-                * material.scattering_pdf(&ray, &rec, &mut scattered)
-                * ray_color(&scattered, &background, world, depth - 1)
-                / pdf;
+        if !survives_roulette(&mut throughput, bounce, roulette_depth, sampler) {
+            break;
+        }
     }
 
-    *background
+    radiance
+}
+
+/// Path tracer with next event estimation: every diffuse bounce also sends a shadow
+/// ray towards the lights. Lights found by both the shadow ray and the material
+/// bounce are weighted with the power heuristic so they aren't counted twice.
+///
+/// Bounces are followed in a loop, carrying the throughput of the path so far.
+/// After `roulette_depth` bounces paths are ended at random, more likely the less
+/// light they still carry, and the survivors are scaled up to make up for it, so
+/// `depth` can be large without darkening the image or growing the stack.
+pub fn ray_color_nee<T: Hittable>(
+    ray: &Ray,
+    background: &Background,
+    world: &T,
+    lights: &HittableList,
+    depth: i32,
+    roulette_depth: i32,
+    sampler: &mut dyn Sampler,
+) -> Radiance {
+    let mut radiance = Radiance::default();
+    let mut throughput = Color::new(1.0, 1.0, 1.0);
+    let mut ray = *ray;
+    // density the last bounce picked `ray` with, none after the camera and specular
+    // bounces, which the shadow rays can't reproduce
+    let mut bsdf_pdf: Option<f32> = None;
+
+    for bounce in 0..depth {
+        let mut rec = HitRecord::default();
+
+        if !world.hit(&ray, 0.001, f32::MAX, &mut rec, sampler) || rec.material.is_none() {
+            if bounce == 0 {
+                radiance.first_hit = miss_features(&ray, background);
+            }
+            radiance.add(bounce, throughput * background.value(&ray));
+            break;
+        }
+
+        if let Some(override_color) = rec.override_color {
+            if bounce == 0 {
+                radiance.first_hit = hit_features(&ray, &rec, override_color);
+            }
+            radiance.add(bounce, throughput * override_color);
+            break;
+        }
+
+        let material = rec.material.clone().unwrap();
+        let mut srec = ScatterRecord::default();
+        let mut emitted = material.emitted(&rec);
+
+        if let Some(bsdf_pdf) = bsdf_pdf {
+            if !emitted.near_zero() {
+                let light_pdf = lights.pdf_value(&ray.orig, &ray.dir, sampler);
+                emitted *= power_heuristic(bsdf_pdf, light_pdf);
+            }
+        }
+
+        radiance.add(bounce, throughput * emitted);
+
+        // the first hit isn't weighted against the lights, its emission is as is
+        let scatters = material.scatter(&ray, &rec, &mut srec, sampler);
+        if bounce == 0 {
+            radiance.first_hit = surface_features(&ray, &rec, emitted, &srec);
+        }
+        if !scatters {
+            break;
+        }
+
+        if let Some(specular_ray) = srec.specular_ray {
+            throughput = throughput * srec.attenuation;
+            ray = specular_ray;
+            bsdf_pdf = None;
+        } else {
+            let material_pdf = match &srec.pdf {
+                Some(pdf) => pdf.as_ref(),
+                None => break,
+            };
+
+            let direct = sample_lights(
+                &ray,
+                &rec,
+                material.as_ref(),
+                material_pdf,
+                world,
+                lights,
+                sampler,
+            );
+            // the light sampled bounces off this surface
+            radiance.add(bounce + 1, throughput * srec.attenuation * direct);
+
+            let mut scattered = Ray::with_time(
+                rec.p,
+                material_pdf.generate(sampler).unit_vector(),
+                ray.time,
+            );
+            let pdf_value = material_pdf.value(&scattered.dir, sampler);
+
+            if pdf_value <= 0.0 {
+                break;
+            }
+
+            throughput =
+                throughput * srec.attenuation * material.scattering_pdf(&ray, &rec, &mut scattered)
+                    / pdf_value;
+            ray = scattered;
+            bsdf_pdf = Some(pdf_value);
+        }
+
+        if !survives_roulette(&mut throughput, bounce, roulette_depth, sampler) {
+            break;
+        }
+    }
+
+    radiance
+}
+
+/// Russian roulette after `bounce`: from `roulette_depth` bounces on paths end at
+/// random, more likely the less light they still carry, and the survivors are
+/// scaled up to make up for it.
+fn survives_roulette(
+    throughput: &mut Color,
+    bounce: i32,
+    roulette_depth: i32,
+    sampler: &mut dyn Sampler,
+) -> bool {
+    if bounce + 1 < roulette_depth {
+        return true;
+    }
+
+    let survival = throughput.x.max(throughput.y).max(throughput.z).min(0.95);
+    if sampler.get_1d() >= survival {
+        return false;
+    }
+    *throughput /= survival;
+
+    true
+}
+
+/// Light reaching `rec` through one shadow ray towards a random point of the lights.
+fn sample_lights<T: Hittable>(
+    ray: &Ray,
+    rec: &HitRecord,
+    material: &dyn Material,
+    material_pdf: &dyn Pdf,
+    world: &T,
+    lights: &HittableList,
+    sampler: &mut dyn Sampler,
+) -> Color {
+    if lights.is_empty() {
+        return Color::new(0.0, 0.0, 0.0);
+    }
+
+    let mut shadow_ray = Ray::with_time(
+        rec.p,
+        lights.random(&rec.p, sampler).unit_vector(),
+        ray.time,
+    );
+    let light_pdf = lights.pdf_value(&rec.p, &shadow_ray.dir, sampler);
+    let scattering_pdf = material.scattering_pdf(ray, rec, &mut shadow_ray);
+
+    if light_pdf <= 0.0 || scattering_pdf <= 0.0 {
+        return Color::new(0.0, 0.0, 0.0);
+    }
+
+    // whatever is hit first is the light seen, which also accounts for occluders,
+    // and emitted() checks the side it is seen from
+    let mut light_rec = HitRecord::default();
+    if !world.hit(&shadow_ray, 0.001, f32::MAX, &mut light_rec, sampler) {
+        return Color::new(0.0, 0.0, 0.0);
+    }
+
+    let emitted = match &light_rec.material {
+        Some(light) => light.emitted(&light_rec),
+        None => return Color::new(0.0, 0.0, 0.0),
+    };
+
+    emitted
+        * scattering_pdf
+        * power_heuristic(light_pdf, material_pdf.value(&shadow_ray.dir, sampler))
+        / light_pdf
+}
+
+/// Weight of a sample drawn with density `f` when density `g` could also have made it.
+pub fn power_heuristic(f: f32, g: f32) -> f32 {
+    f * f / (f * f + g * g)
 }
 
 pub fn create_default_camera() -> Camera {
@@ -0,0 +1,232 @@
+use std::env;
+use std::fmt;
+use std::path::Path;
+use std::process;
+use std::str::FromStr;
+
+use ray_tracing::*;
+
+const USAGE: &str = "usage: denoise IMAGE [OPTIONS]
+
+Filters the noise out of a rendered image. The albedo, normal and depth images
+written next to it by render --aov guide the filter when they are found, like
+cornell.albedo.exr for cornell.exr.
+
+options:
+    --albedo <PATH>             albedo image (default <IMAGE>.albedo.*)
+    --normal <PATH>             normal image (default <IMAGE>.normal.*)
+    --depth <PATH>              depth image (default <IMAGE>.depth.*, the raw depths
+                                in <IMAGE>.depth.exr next to an 8 or 16 bit one)
+    --output <PATH>             denoised image (default <IMAGE>.denoised.*)
+    --format <ldr|png16|exr|hdr>
+                                output format instead of the one of the extension
+    --iterations <N>            filter passes, each reaching twice as far (default 5)
+    --sigma-luminance <S>       brightness differences blurred, in noise deviations (default 4)
+    --sigma-normal <S>          exponent of the normal weights, higher keeps creases (default 128)
+    --sigma-depth <S>           depth differences blurred, relative to the slope (default 1)
+    --help                      print this message";
+
+#[derive(Default)]
+struct Options {
+    image: Option<String>,
+    albedo: Option<String>,
+    normal: Option<String>,
+    depth: Option<String>,
+    path: Option<String>,
+    format: Option<OutputFormat>,
+    settings: DenoiseSettings,
+}
+
+enum DenoiseError {
+    Output(OutputError),
+    /// A guide that was asked for doesn't match the image.
+    Size {
+        path: String,
+        width: u32,
+        height: u32,
+    },
+}
+
+impl fmt::Display for DenoiseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DenoiseError::Output(e) => write!(f, "{}", e),
+            DenoiseError::Size {
+                path,
+                width,
+                height,
+            } => write!(
+                f,
+                "{}: the guide isn't {}x{} like the image",
+                path, width, height
+            ),
+        }
+    }
+}
+
+impl From<OutputError> for DenoiseError {
+    fn from(e: OutputError) -> Self {
+        DenoiseError::Output(e)
+    }
+}
+
+fn main() {
+    let options = match parse_args(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{}\n\n{}", message, USAGE);
+            process::exit(2);
+        }
+    };
+
+    let image = match &options.image {
+        Some(image) => image.clone(),
+        None => {
+            eprintln!("missing image\n\n{}", USAGE);
+            process::exit(2);
+        }
+    };
+
+    if let Err(e) = run(&image, options) {
+        eprintln!("failed to denoise {}: {}", image, e);
+        process::exit(1);
+    }
+}
+
+fn run(image: &str, options: Options) -> Result<(), DenoiseError> {
+    let color = Framebuffer::load(image)?;
+
+    // a guide that wasn't asked for is only used if it is there
+    let guide = |path: Option<String>, name: &str| -> Result<Option<Framebuffer>, DenoiseError> {
+        let (mut path, required) = match path {
+            Some(path) => (path, true),
+            None => (companion_path(image, name), false),
+        };
+
+        if !required && !Path::new(&path).exists() {
+            return Ok(None);
+        }
+
+        // render writes the raw depths next to a depth preview
+        let raw_depth = Path::new(&path).with_extension("exr");
+        if name == "depth"
+            && OutputFormat::from_path(&path) == OutputFormat::Ldr
+            && raw_depth.exists()
+        {
+            path = raw_depth.to_string_lossy().into_owned();
+        }
+
+        println!("guided by {}", path);
+        let mut framebuffer = Framebuffer::load(&path)?;
+        if framebuffer.width != color.width || framebuffer.height != color.height {
+            if required {
+                return Err(DenoiseError::Size {
+                    path,
+                    width: color.width,
+                    height: color.height,
+                });
+            }
+
+            eprintln!("{} isn't the size of the image, ignored", path);
+            return Ok(None);
+        }
+
+        // 8 and 16 bit normals and depths are stored as previews, the values they
+        // came from are the channels before decoding. Depth previews only keep
+        // the order of the depths, flipped and squeezed into the range of the
+        // image, which the depth weights mostly see through but not quite.
+        if name != "albedo" && OutputFormat::from_path(&path) == OutputFormat::Ldr {
+            for c in framebuffer.pixels.iter_mut() {
+                *c = Color::new(
+                    linear_to_srgb(c.x),
+                    linear_to_srgb(c.y),
+                    linear_to_srgb(c.z),
+                );
+                if name == "normal" {
+                    *c = 2.0 * *c - Color::new(1.0, 1.0, 1.0);
+                }
+            }
+        }
+
+        Ok(Some(framebuffer))
+    };
+
+    let guides = DenoiseGuides {
+        albedo: guide(options.albedo, "albedo")?,
+        normal: guide(options.normal, "normal")?,
+        depth: guide(options.depth, "depth")?,
+        variance: None,
+    };
+
+    let denoised = denoise(&color, &guides, &options.settings);
+
+    let path = options
+        .path
+        .unwrap_or_else(|| companion_path(image, "denoised"));
+    let format = options
+        .format
+        .unwrap_or_else(|| OutputFormat::from_path(&path));
+
+    println!("saving -> {}", path);
+    denoised.save(&path, format, &DisplaySettings::default())?;
+
+    Ok(())
+}
+
+fn parse_args<I: Iterator<Item = String>>(mut args: I) -> Result<Options, String> {
+    let mut options = Options::default();
+
+    while let Some(arg) = args.next() {
+        if !arg.starts_with("--") {
+            if options.image.is_some() {
+                return Err(format!("unexpected argument {}", arg));
+            }
+            options.image = Some(arg);
+            continue;
+        }
+
+        let (name, inline_value) = match arg.find('=') {
+            Some(i) => (arg[..i].to_string(), Some(arg[i + 1..].to_string())),
+            None => (arg.clone(), None),
+        };
+
+        if name == "--help" {
+            println!("{}", USAGE);
+            process::exit(0);
+        }
+
+        let value = match inline_value.or_else(|| args.next()) {
+            Some(value) => value,
+            None => return Err(format!("missing value for {}", name)),
+        };
+
+        match name.as_str() {
+            "--albedo" => options.albedo = Some(value),
+            "--normal" => options.normal = Some(value),
+            "--depth" => options.depth = Some(value),
+            "--output" => options.path = Some(value),
+            "--format" => {
+                options.format = Some(match value.as_str() {
+                    "ldr" => OutputFormat::Ldr,
+                    "png16" => OutputFormat::Png16,
+                    "exr" => OutputFormat::Exr,
+                    "hdr" => OutputFormat::Hdr,
+                    _ => return Err(format!("unknown format {}", value)),
+                })
+            }
+            "--iterations" => options.settings.iterations = parse_value(&name, &value)?,
+            "--sigma-luminance" => options.settings.sigma_luminance = parse_value(&name, &value)?,
+            "--sigma-normal" => options.settings.sigma_normal = parse_value(&name, &value)?,
+            "--sigma-depth" => options.settings.sigma_depth = parse_value(&name, &value)?,
+            _ => return Err(format!("unknown option {}", name)),
+        }
+    }
+
+    Ok(options)
+}
+
+fn parse_value<T: FromStr>(name: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid value {} for {}", value, name))
+}
@@ -1,5 +1,11 @@
 pub mod common;
 pub mod cpu_renderer;
+pub mod progress;
+pub mod scene;
+pub mod scenes;
 
 pub use common::*;
 pub use cpu_renderer::*;
+pub use progress::*;
+pub use scene::*;
+pub use scenes::*;
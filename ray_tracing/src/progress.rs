@@ -0,0 +1,192 @@
+use std::io::{self, Write};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::common::*;
+
+/// Square pixels are rendered in, small enough to balance the threads and keep
+/// the rays of a tile close together in the BVH.
+pub const DEFAULT_TILE_SIZE: u32 = 32;
+
+/// A block of the image, rows from the top like the framebuffer.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Tile {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Tile {
+    /// Splits an image into tiles of `size` pixels, row by row. Tiles on the right
+    /// and bottom edges are cut to fit.
+    pub fn split(width: u32, height: u32, size: u32) -> Vec<Tile> {
+        let size = size.max(1);
+        let mut tiles = vec![];
+
+        for y in (0..height).step_by(size as usize) {
+            for x in (0..width).step_by(size as usize) {
+                tiles.push(Tile {
+                    x,
+                    y,
+                    width: size.min(width - x),
+                    height: size.min(height - y),
+                });
+            }
+        }
+
+        tiles
+    }
+
+    pub fn pixel_count(&self) -> usize {
+        (self.width * self.height) as usize
+    }
+
+    /// The tile with `margin` more pixels on every side, cut to an image of
+    /// `width` by `height`.
+    pub fn grown(&self, margin: u32, width: u32, height: u32) -> Tile {
+        let (x, y) = (self.x.saturating_sub(margin), self.y.saturating_sub(margin));
+
+        Tile {
+            x,
+            y,
+            width: (self.x + self.width + margin).min(width) - x,
+            height: (self.y + self.height + margin).min(height) - y,
+        }
+    }
+}
+
+/// Where a render is at when an observer is called.
+#[derive(Debug, Copy, Clone)]
+pub struct Progress {
+    /// Pass being rendered, from zero.
+    pub pass: u32,
+    pub pass_count: u32,
+    pub tiles_done: usize,
+    pub tile_count: usize,
+    /// Samples per pixel once the current pass is done.
+    pub samples: u32,
+    pub samples_per_pixel: u32,
+    pub elapsed: Duration,
+    /// Share of the work of this run that is done, from zero to one. Samples of a
+    /// resumed checkpoint don't count.
+    pub done: f32,
+}
+
+impl Progress {
+    /// Estimated time left, assuming the rest goes as fast as so far.
+    pub fn eta(&self) -> Option<Duration> {
+        if self.done <= 0.0 {
+            return None;
+        }
+
+        Some(self.elapsed.mul_f32((1.0 - self.done) / self.done))
+    }
+}
+
+/// Watches a render from an embedding application. Methods are called from the
+/// rendering threads, as tiles finish in no particular order.
+pub trait RenderObserver: Sync + Send {
+    /// `pixels` are the tile's averages so far, row by row, and `time` how long the
+    /// tile took to render.
+    fn tile_done(&self, _progress: &Progress, _tile: &Tile, _pixels: &[Color], _time: Duration) {}
+
+    fn pass_done(&self, _progress: &Progress) {}
+
+    /// Checked before every tile. A cancelled render drops its unfinished pass,
+    /// saves the passes it finished without denoising them, if there are any, and
+    /// returns `RenderError::Cancelled`.
+    fn cancelled(&self) -> bool {
+        false
+    }
+}
+
+/// Progress bar with the ETA on stderr, one line per pass.
+pub struct ConsoleProgress {
+    state: Mutex<ConsoleState>,
+}
+
+struct ConsoleState {
+    last_draw: Option<Instant>,
+    slowest_tile: Duration,
+    tile_time: Duration,
+    tiles: u32,
+}
+
+const BAR_WIDTH: usize = 30;
+const REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+
+impl ConsoleProgress {
+    pub fn new() -> Self {
+        Self {
+            state: Mutex::new(ConsoleState {
+                last_draw: None,
+                slowest_tile: Duration::default(),
+                tile_time: Duration::default(),
+                tiles: 0,
+            }),
+        }
+    }
+
+    fn draw(progress: &Progress) {
+        let pass_done = progress.tiles_done as f32 / progress.tile_count.max(1) as f32;
+        let filled = (pass_done * BAR_WIDTH as f32) as usize;
+        let eta = match progress.eta() {
+            Some(eta) => format!("{:.0} s", eta.as_secs_f32()),
+            None => String::from("?"),
+        };
+
+        eprint!(
+            "\rpass {}/{} [{}{}] {:3.0}% {} spp, {:.1} s, eta {}   ",
+            progress.pass + 1,
+            progress.pass_count,
+            "=".repeat(filled),
+            " ".repeat(BAR_WIDTH - filled),
+            100.0 * pass_done,
+            progress.samples,
+            progress.elapsed.as_secs_f32(),
+            eta
+        );
+        io::stderr().flush().ok();
+    }
+}
+
+impl Default for ConsoleProgress {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RenderObserver for ConsoleProgress {
+    fn tile_done(&self, progress: &Progress, _tile: &Tile, _pixels: &[Color], time: Duration) {
+        let mut state = self.state.lock().unwrap();
+
+        state.slowest_tile = state.slowest_tile.max(time);
+        state.tile_time += time;
+        state.tiles += 1;
+
+        if state
+            .last_draw
+            .is_none_or(|last| last.elapsed() >= REDRAW_INTERVAL)
+        {
+            state.last_draw = Some(Instant::now());
+            Self::draw(progress);
+        }
+    }
+
+    fn pass_done(&self, progress: &Progress) {
+        let mut state = self.state.lock().unwrap();
+
+        Self::draw(progress);
+        eprintln!();
+
+        if progress.pass + 1 == progress.pass_count && state.tiles > 0 {
+            eprintln!(
+                "tiles took {:.1} ms on average, {:.1} ms at most",
+                state.tile_time.as_secs_f32() * 1000.0 / state.tiles as f32,
+                state.slowest_tile.as_secs_f32() * 1000.0
+            );
+        }
+        state.last_draw = None;
+    }
+}
@@ -0,0 +1,354 @@
+use std::env;
+use std::fs;
+use std::process;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use ray_tracing::*;
+
+const USAGE: &str = "usage: render [SCENE] [OPTIONS]
+
+SCENE is one_weekend, next_week (default), the name of a file in assets/scenes
+or a path to a .json scene file.
+
+options:
+    --preset <fast|precise>     quality preset (default fast)
+    --width <PIXELS>            image width
+    --aspect-ratio <RATIO>      image aspect ratio, e.g. 1.7778
+    --spp <N>                   samples per pixel
+    --max-depth <N>             maximum ray bounces
+    --roulette-depth <N>        bounces before paths may end at random
+    --integrator <mixture|nee>  light transport, mixture sampling or next event estimation
+    --sampler <independent|stratified|halton|sobol>
+                                how samples are spread over each pixel (default sobol)
+    --filter <box|tent|gaussian|mitchell|lanczos>
+                                how samples are spread over the pixels around them (default box)
+    --filter-radius <PIXELS>    reach of the filter, from 0.5 for box to 3 for lanczos by default
+    --output <PATH>             output image, .exr and .hdr keep the full dynamic range
+    --format <ldr|png16|exr|hdr>
+                                output format instead of the one of the extension
+    --tone-mapping <clamp|reinhard|aces>
+                                curve fitting bright images into 8 and 16 bit outputs
+    --exposure <STOPS>          brightens or darkens 8 and 16 bit outputs
+    --pass-spp <N>              render progressively, adding N samples per pass (default 16)
+    --checkpoint <PATH>         checkpoint of a progressive render (default <OUTPUT>.checkpoint)
+    --checkpoint-interval <S>   seconds between intermediate images and checkpoints (default 60)
+    --resume                    carry on from the checkpoint instead of starting over
+    --adaptive <NOISE>          stop sampling pixels less noisy than this, e.g. 0.01,
+                                --spp becomes the most a pixel takes
+    --min-spp <N>               samples every pixel takes before it may stop (default 16)
+    --heatmap <PATH>            map of the samples taken (default <OUTPUT>.samples.png)
+    --denoise                   filter the noise out, the noisy image goes to <OUTPUT>.noisy.*
+    --aov <LIST>                extra images next to the output, comma separated or all:
+                                albedo, normal, depth, uv, material_id, object_id,
+                                direct and indirect
+    --seed <N>                  seed for scene generation and sampling
+    --threads <N>               number of rendering threads
+    --tile-size <PIXELS>        side of the square tiles rendered at a time (default 32)
+    --help                      print this message";
+
+#[derive(Default)]
+struct Options {
+    scene: Option<String>,
+    preset: Option<Preset>,
+    image_width: Option<u32>,
+    aspect_ratio: Option<f32>,
+    samples_per_pixel: Option<u32>,
+    max_depth: Option<i32>,
+    roulette_depth: Option<i32>,
+    integrator: Option<Integrator>,
+    sampler: Option<SamplerKind>,
+    filter: Option<FilterKind>,
+    filter_radius: Option<f32>,
+    path: Option<String>,
+    format: Option<OutputFormat>,
+    tone_mapping: Option<ToneMapping>,
+    exposure: Option<f32>,
+    pass_samples: Option<u32>,
+    checkpoint: Option<String>,
+    checkpoint_interval: Option<f32>,
+    resume: bool,
+    adaptive_threshold: Option<f32>,
+    min_samples: Option<u32>,
+    heatmap: Option<String>,
+    aovs: Option<Vec<Aov>>,
+    denoise: bool,
+    seed: Option<u64>,
+    threads: Option<usize>,
+    tile_size: Option<u32>,
+}
+
+fn main() {
+    let options = match parse_args(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{}\n\n{}", message, USAGE);
+            process::exit(2);
+        }
+    };
+
+    if let Some(threads) = options.threads {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build_global()
+            .unwrap();
+    }
+
+    let scene = options
+        .scene
+        .clone()
+        .unwrap_or_else(|| String::from("next_week"));
+    let preset = options.preset.unwrap_or(Preset::Fast);
+
+    let mut params = match load_named_scene(&scene, preset, options.seed) {
+        Ok(params) => params,
+        Err(e) => {
+            eprintln!("failed to load scene {}: {}", scene, e);
+            process::exit(1);
+        }
+    };
+
+    if let Some(image_width) = options.image_width {
+        params.image_width = image_width;
+    }
+    if let Some(aspect_ratio) = options.aspect_ratio {
+        params.camera.set_aspect_ratio(aspect_ratio);
+        params.aspect_ratio = aspect_ratio;
+    }
+    if let Some(samples_per_pixel) = options.samples_per_pixel {
+        params.samples_per_pixel = samples_per_pixel;
+    }
+    if let Some(max_depth) = options.max_depth {
+        params.max_depth = max_depth;
+    }
+    if let Some(roulette_depth) = options.roulette_depth {
+        params.roulette_depth = roulette_depth;
+    }
+    if let Some(integrator) = options.integrator {
+        params.integrator = integrator;
+    }
+    if let Some(sampler) = options.sampler {
+        params.sampler = sampler;
+    }
+    // another filter comes with its own radius unless one is given
+    if options.filter.is_some() || options.filter_radius.is_some() {
+        let kind = options.filter.unwrap_or(params.filter.kind);
+        params.filter = Filter::new(kind, options.filter_radius);
+    }
+    if let Some(path) = options.path {
+        params.path = path;
+    }
+    if let Some(format) = options.format {
+        params.format = Some(format);
+    }
+    if let Some(tone_mapping) = options.tone_mapping {
+        params.display.tone_mapping = tone_mapping;
+    }
+    if let Some(exposure) = options.exposure {
+        params.display.exposure = exposure;
+    }
+
+    // any of the progressive options turns progressive rendering on
+    if options.pass_samples.is_some()
+        || options.checkpoint.is_some()
+        || options.checkpoint_interval.is_some()
+        || options.resume
+    {
+        let mut progressive = ProgressiveSettings {
+            checkpoint: options.checkpoint,
+            resume: options.resume,
+            scene: scene_identity(&scene, preset),
+            ..ProgressiveSettings::default()
+        };
+        if let Some(pass_samples) = options.pass_samples {
+            progressive.pass_samples = pass_samples;
+        }
+        if let Some(interval) = options.checkpoint_interval {
+            progressive.interval = interval;
+        }
+        params.progressive = Some(progressive);
+    }
+
+    // as do the adaptive ones for adaptive sampling, on top of the scene's settings
+    if options.adaptive_threshold.is_some()
+        || options.min_samples.is_some()
+        || options.heatmap.is_some()
+    {
+        let mut adaptive = params.adaptive.take().unwrap_or_default();
+        if let Some(threshold) = options.adaptive_threshold {
+            adaptive.threshold = threshold;
+        }
+        if let Some(min_samples) = options.min_samples {
+            adaptive.min_samples = min_samples;
+        }
+        if options.heatmap.is_some() {
+            adaptive.heatmap = options.heatmap;
+        }
+        params.adaptive = Some(adaptive);
+    }
+
+    if let Some(aovs) = options.aovs {
+        params.aovs = aovs;
+    }
+    if options.denoise && params.denoise.is_none() {
+        params.denoise = Some(DenoiseSettings::default());
+    }
+    if let Some(tile_size) = options.tile_size {
+        params.tile_size = tile_size;
+    }
+    params.observer = Some(Arc::new(ConsoleProgress::new()));
+
+    println!("rendering -> {}", scene);
+
+    if let Err(e) = render_world_cpu(params) {
+        eprintln!("failed to render {}: {}", scene, e);
+        process::exit(1);
+    }
+}
+
+/// What a checkpoint is told about the scene: the preset, and the scene file with
+/// a hash of its contents, so editing it makes the checkpoint stale. Procedural
+/// scenes are told apart by their seed.
+fn scene_identity(scene: &str, preset: Preset) -> String {
+    match scene_file(scene) {
+        Some(path) => format!(
+            "{}|{:x}|{:?}",
+            fs::canonicalize(&path).map_or(path.clone(), |path| path.display().to_string()),
+            settings_hash(&fs::read(&path).unwrap_or_default()),
+            preset
+        ),
+        None => format!("{}|{:?}", scene, preset),
+    }
+}
+
+fn parse_args<I: Iterator<Item = String>>(mut args: I) -> Result<Options, String> {
+    let mut options = Options::default();
+
+    while let Some(arg) = args.next() {
+        if !arg.starts_with("--") {
+            if options.scene.is_some() {
+                return Err(format!("unexpected argument {}", arg));
+            }
+            options.scene = Some(arg);
+            continue;
+        }
+
+        let (name, inline_value) = match arg.find('=') {
+            Some(i) => (arg[..i].to_string(), Some(arg[i + 1..].to_string())),
+            None => (arg.clone(), None),
+        };
+
+        if name == "--help" {
+            println!("{}", USAGE);
+            process::exit(0);
+        }
+
+        if name == "--resume" {
+            options.resume = true;
+            continue;
+        }
+
+        if name == "--denoise" {
+            options.denoise = true;
+            continue;
+        }
+
+        let value = match inline_value.or_else(|| args.next()) {
+            Some(value) => value,
+            None => return Err(format!("missing value for {}", name)),
+        };
+
+        match name.as_str() {
+            "--preset" => {
+                options.preset = Some(match value.as_str() {
+                    "fast" => Preset::Fast,
+                    "precise" => Preset::Precise,
+                    _ => return Err(format!("unknown preset {}", value)),
+                })
+            }
+            "--width" => options.image_width = Some(parse_value(&name, &value)?),
+            "--aspect-ratio" => options.aspect_ratio = Some(parse_value(&name, &value)?),
+            "--spp" => options.samples_per_pixel = Some(parse_value(&name, &value)?),
+            "--max-depth" => options.max_depth = Some(parse_value(&name, &value)?),
+            "--roulette-depth" => options.roulette_depth = Some(parse_value(&name, &value)?),
+            "--integrator" => {
+                options.integrator = Some(match value.as_str() {
+                    "mixture" => Integrator::Mixture,
+                    "nee" => Integrator::Nee,
+                    _ => return Err(format!("unknown integrator {}", value)),
+                })
+            }
+            "--sampler" => {
+                options.sampler = Some(match value.as_str() {
+                    "independent" => SamplerKind::Independent,
+                    "stratified" => SamplerKind::Stratified,
+                    "halton" => SamplerKind::Halton,
+                    "sobol" => SamplerKind::Sobol,
+                    _ => return Err(format!("unknown sampler {}", value)),
+                })
+            }
+            "--filter" => {
+                options.filter = Some(match value.as_str() {
+                    "box" => FilterKind::Box,
+                    "tent" => FilterKind::Tent,
+                    "gaussian" => FilterKind::Gaussian,
+                    "mitchell" => FilterKind::Mitchell,
+                    "lanczos" => FilterKind::Lanczos,
+                    _ => return Err(format!("unknown filter {}", value)),
+                })
+            }
+            "--filter-radius" => options.filter_radius = Some(parse_value(&name, &value)?),
+            "--output" => options.path = Some(value),
+            "--format" => {
+                options.format = Some(match value.as_str() {
+                    "ldr" => OutputFormat::Ldr,
+                    "png16" => OutputFormat::Png16,
+                    "exr" => OutputFormat::Exr,
+                    "hdr" => OutputFormat::Hdr,
+                    _ => return Err(format!("unknown format {}", value)),
+                })
+            }
+            "--tone-mapping" => {
+                options.tone_mapping = Some(match value.as_str() {
+                    "clamp" => ToneMapping::Clamp,
+                    "reinhard" => ToneMapping::Reinhard,
+                    "aces" => ToneMapping::Aces,
+                    _ => return Err(format!("unknown tone mapping {}", value)),
+                })
+            }
+            "--exposure" => options.exposure = Some(parse_value(&name, &value)?),
+            "--pass-spp" => options.pass_samples = Some(parse_value(&name, &value)?),
+            "--checkpoint" => options.checkpoint = Some(value),
+            "--checkpoint-interval" => {
+                options.checkpoint_interval = Some(parse_value(&name, &value)?)
+            }
+            "--adaptive" => options.adaptive_threshold = Some(parse_value(&name, &value)?),
+            "--min-spp" => options.min_samples = Some(parse_value(&name, &value)?),
+            "--heatmap" => options.heatmap = Some(value),
+            "--aov" => options.aovs = Some(parse_aovs(&value)?),
+            "--seed" => options.seed = Some(parse_value(&name, &value)?),
+            "--threads" => options.threads = Some(parse_value(&name, &value)?),
+            "--tile-size" => options.tile_size = Some(parse_value(&name, &value)?),
+            _ => return Err(format!("unknown option {}", name)),
+        }
+    }
+
+    Ok(options)
+}
+
+fn parse_aovs(value: &str) -> Result<Vec<Aov>, String> {
+    if value == "all" {
+        return Ok(Aov::ALL.to_vec());
+    }
+
+    value
+        .split(',')
+        .map(|name| Aov::from_name(name.trim()).ok_or_else(|| format!("unknown aov {}", name)))
+        .collect()
+}
+
+fn parse_value<T: FromStr>(name: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid value {} for {}", value, name))
+}
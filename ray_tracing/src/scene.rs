@@ -0,0 +1,1225 @@
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use nalgebra as na;
+use serde::Deserialize;
+
+use crate::common::*;
+use crate::cpu_renderer::*;
+use crate::progress::*;
+
+type Vec3Description = [f32; 3];
+type Vec2Description = [f32; 2];
+
+#[derive(Deserialize, Debug)]
+pub struct SceneDescription {
+    pub camera: CameraDescription,
+    #[serde(default)]
+    pub render: RenderDescription,
+    #[serde(default)]
+    pub background: BackgroundDescription,
+    #[serde(default)]
+    pub textures: HashMap<String, TextureDescription>,
+    #[serde(default)]
+    pub materials: HashMap<String, MaterialDescription>,
+    pub objects: Vec<ObjectDescription>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct CameraDescription {
+    pub look_from: Vec3Description,
+    pub look_at: Vec3Description,
+    #[serde(default = "default_v_up")]
+    pub v_up: Vec3Description,
+    pub vfov: f32,
+    #[serde(default)]
+    pub aperture: f32,
+    #[serde(default = "default_focus_dist")]
+    pub focus_dist: f32,
+    /// Shutter open and close times for motion blur.
+    #[serde(default)]
+    pub shutter: [f32; 2],
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+pub struct RenderDescription {
+    pub image_width: u32,
+    pub aspect_ratio: f32,
+    pub samples_per_pixel: u32,
+    pub max_depth: i32,
+    pub roulette_depth: i32,
+    pub path: Option<String>,
+    /// Overrides the format guessed from the extension of `path`.
+    pub format: Option<OutputFormat>,
+    pub tone_mapping: ToneMapping,
+    /// Stops of exposure applied before tone mapping.
+    pub exposure: f32,
+    pub integrator: Integrator,
+    pub sampler: SamplerKind,
+    pub filter: FilterKind,
+    /// In pixels, the usual radius of `filter` when unset.
+    pub filter_radius: Option<f32>,
+    pub adaptive: Option<AdaptiveSettings>,
+    /// Extra images written next to the render.
+    pub aovs: Vec<Aov>,
+    pub denoise: Option<DenoiseSettings>,
+    /// Quality used instead of the settings above when rendering with `Preset::Precise`.
+    pub precise: Option<QualityDescription>,
+}
+
+impl Default for RenderDescription {
+    fn default() -> Self {
+        Self {
+            image_width: 400,
+            aspect_ratio: 16.0 / 9.0,
+            samples_per_pixel: 100,
+            max_depth: 50,
+            roulette_depth: DEFAULT_ROULETTE_DEPTH,
+            path: None,
+            format: None,
+            tone_mapping: ToneMapping::default(),
+            exposure: 0.0,
+            integrator: Integrator::default(),
+            sampler: SamplerKind::default(),
+            filter: FilterKind::default(),
+            filter_radius: None,
+            adaptive: None,
+            aovs: vec![],
+            denoise: None,
+            precise: None,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct QualityDescription {
+    pub image_width: u32,
+    pub samples_per_pixel: u32,
+    pub max_depth: i32,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+pub enum BackgroundDescription {
+    Color(Vec3Description),
+    Sky { sky: SkyDescription },
+}
+
+impl Default for BackgroundDescription {
+    fn default() -> Self {
+        BackgroundDescription::Color([0.0, 0.0, 0.0])
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SkyDescription {
+    pub horizon: Vec3Description,
+    pub zenith: Vec3Description,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Preset {
+    Fast,
+    Precise,
+}
+
+/// Either an inline color or the name of an entry in `textures`.
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+pub enum TextureReference {
+    Color(Vec3Description),
+    Named(String),
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TextureDescription {
+    Solid {
+        color: Vec3Description,
+    },
+    Checker {
+        even: TextureReference,
+        odd: TextureReference,
+        size: f32,
+    },
+    DebugUv,
+    Image {
+        path: String,
+        #[serde(default = "default_filtering")]
+        filtering: TextureFiltering,
+        #[serde(default = "default_flip")]
+        flip: TextureFlip,
+        #[serde(default = "default_repeating")]
+        repeating: Vec2Description,
+    },
+    Noise {
+        #[serde(flatten)]
+        noise: NoiseDescription,
+    },
+    Turbulence {
+        #[serde(flatten)]
+        noise: NoiseDescription,
+        #[serde(default = "default_depth")]
+        depth: usize,
+    },
+    Fbm {
+        #[serde(flatten)]
+        noise: NoiseDescription,
+        #[serde(default = "default_octaves")]
+        octaves: usize,
+        #[serde(default = "default_lacunarity")]
+        lacunarity: f32,
+        #[serde(default = "default_gain")]
+        gain: f32,
+    },
+    Marble {
+        #[serde(flatten)]
+        noise: NoiseDescription,
+        #[serde(default = "default_depth")]
+        depth: usize,
+    },
+    Wood {
+        #[serde(flatten)]
+        noise: NoiseDescription,
+        #[serde(default = "default_depth")]
+        depth: usize,
+    },
+}
+
+/// Settings shared by the noise textures, which blend from `low` to `high`.
+#[derive(Deserialize, Debug)]
+pub struct NoiseDescription {
+    #[serde(default)]
+    pub seed: u64,
+    #[serde(default = "default_noise_scale")]
+    pub scale: f32,
+    #[serde(default)]
+    pub low: Vec3Description,
+    #[serde(default = "default_high")]
+    pub high: Vec3Description,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum MaterialDescription {
+    Lambertian {
+        albedo: TextureReference,
+    },
+    Metal {
+        albedo: Vec3Description,
+        #[serde(default)]
+        fuzz: f32,
+    },
+    Dielectric {
+        index_of_refraction: f32,
+    },
+    DiffuseLight {
+        color: Vec3Description,
+        #[serde(default = "default_side")]
+        side: Side,
+    },
+    /// Phase function of volumes.
+    Isotropic {
+        albedo: TextureReference,
+    },
+    Debug {
+        target: DebugTarget,
+    },
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum TransformDescription {
+    Translate(Vec3Description),
+    RotateX(f32),
+    RotateY(f32),
+    RotateZ(f32),
+    Scale(ScaleDescription),
+    /// Row major affine matrix.
+    Matrix([[f32; 4]; 4]),
+    Keyframes(Vec<KeyframeDescription>),
+}
+
+#[derive(Deserialize, Debug)]
+pub struct KeyframeDescription {
+    pub time: f32,
+    #[serde(default)]
+    pub translate: Vec3Description,
+    /// Degrees around x, y and z, applied in that order.
+    #[serde(default)]
+    pub rotate: Vec3Description,
+    #[serde(default = "default_scale")]
+    pub scale: ScaleDescription,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+pub enum ScaleDescription {
+    Uniform(f32),
+    Axes(Vec3Description),
+}
+
+impl ScaleDescription {
+    pub fn to_vec3(&self) -> Vec3 {
+        match self {
+            ScaleDescription::Uniform(s) => Vec3::new(*s, *s, *s),
+            ScaleDescription::Axes(s) => to_vec3(*s),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ObjectDescription {
+    #[serde(flatten)]
+    pub shape: ShapeDescription,
+    #[serde(default)]
+    pub transforms: Vec<TransformDescription>,
+    /// Samples the object directly as a light. Only top level objects can be lights.
+    #[serde(default)]
+    pub light: bool,
+}
+
+impl ObjectDescription {
+    /// Whether the object knows how to pick directions towards itself, which it
+    /// needs to be a light. Moving objects and volumes don't.
+    pub fn can_be_sampled(&self) -> bool {
+        let moves = self
+            .transforms
+            .iter()
+            .any(|transform| matches!(transform, TransformDescription::Keyframes(_)));
+
+        !moves
+            && match &self.shape {
+                ShapeDescription::MovingSphere { .. }
+                | ShapeDescription::ConstantMedium { .. }
+                | ShapeDescription::GridVolume { .. } => false,
+                ShapeDescription::Group { objects } => {
+                    objects.iter().all(ObjectDescription::can_be_sampled)
+                }
+                _ => true,
+            }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ShapeDescription {
+    Sphere {
+        center: Vec3Description,
+        radius: f32,
+        material: String,
+    },
+    MovingSphere {
+        center0: Vec3Description,
+        center1: Vec3Description,
+        #[serde(default)]
+        time0: f32,
+        #[serde(default = "default_time1")]
+        time1: f32,
+        radius: f32,
+        material: String,
+    },
+    Triangle {
+        position: [Vec3Description; 3],
+        normal: Option<[Vec3Description; 3]>,
+        uv: Option<[Vec2Description; 3]>,
+        material: String,
+    },
+    XyRect {
+        x0: f32,
+        x1: f32,
+        y0: f32,
+        y1: f32,
+        k: f32,
+        material: String,
+    },
+    YzRect {
+        y0: f32,
+        y1: f32,
+        z0: f32,
+        z1: f32,
+        k: f32,
+        material: String,
+    },
+    XzRect {
+        x0: f32,
+        x1: f32,
+        z0: f32,
+        z1: f32,
+        k: f32,
+        material: String,
+    },
+    Box {
+        p0: Vec3Description,
+        p1: Vec3Description,
+        material: String,
+    },
+    /// A mesh in the JSON format of `assets/monkey.json`.
+    Mesh {
+        path: String,
+        /// Key of the mesh in files holding several of them.
+        name: Option<String>,
+        material: String,
+        #[serde(default = "default_normals")]
+        normals: NormalMode,
+    },
+    Gltf {
+        path: String,
+        /// Overrides the materials of the file.
+        material: Option<String>,
+    },
+    Obj {
+        path: String,
+        /// Overrides the materials of the MTL files.
+        material: Option<String>,
+        #[serde(default = "default_normals")]
+        normals: NormalMode,
+    },
+    Group {
+        objects: Vec<ObjectDescription>,
+    },
+    /// Fog filling a closed object, whose own material is ignored.
+    ConstantMedium {
+        boundary: Box<ObjectDescription>,
+        density: f32,
+        albedo: TextureReference,
+    },
+    /// Density grid stretched over the bounding box of a closed object.
+    GridVolume {
+        boundary: Box<ObjectDescription>,
+        grid: GridDescription,
+        /// Scales the grid values.
+        density: f32,
+        albedo: TextureReference,
+    },
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum GridDescription {
+    /// Little endian floats, x varying fastest.
+    Raw { path: String, dims: [usize; 3] },
+    /// A ball fading out towards the sides of the grid.
+    Sphere { resolution: usize },
+}
+
+#[derive(Debug)]
+pub struct SceneError {
+    pub path: String,
+    pub message: String,
+}
+
+impl SceneError {
+    pub fn new(path: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            path: path.into(),
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.path.is_empty() {
+            write!(f, "{}", self.message)
+        } else {
+            write!(f, "{}: {}", self.path, self.message)
+        }
+    }
+}
+
+impl Error for SceneError {}
+
+/// Reads a JSON scene description and builds everything needed to render it.
+/// Relative texture paths are resolved against the directory of the scene file.
+pub fn load_scene<P: AsRef<Path>>(
+    path: P,
+    preset: Preset,
+) -> Result<CPURenderingParams<LinearBVH>, SceneError> {
+    let path = path.as_ref();
+    let source = fs::read_to_string(path)
+        .map_err(|e| SceneError::new("", format!("{}: {}", path.display(), e)))?;
+    let base_dir = path.parent().unwrap_or_else(|| Path::new(""));
+
+    let mut params = parse_scene(&source, base_dir, preset)?;
+
+    if params.path.is_empty() {
+        let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("scene");
+        params.path = output_path(stem, preset);
+    }
+
+    Ok(params)
+}
+
+pub fn parse_scene(
+    source: &str,
+    base_dir: &Path,
+    preset: Preset,
+) -> Result<CPURenderingParams<LinearBVH>, SceneError> {
+    let deserializer = &mut serde_json::Deserializer::from_str(source);
+    let description: SceneDescription = serde_path_to_error::deserialize(deserializer)
+        .map_err(|e| SceneError::new(e.path().to_string(), e.inner().to_string()))?;
+
+    build_scene(&description, base_dir, preset)
+}
+
+pub fn build_scene(
+    description: &SceneDescription,
+    base_dir: &Path,
+    preset: Preset,
+) -> Result<CPURenderingParams<LinearBVH>, SceneError> {
+    let mut builder = SceneBuilder::new(description, base_dir);
+
+    let mut objects = vec![];
+    let mut lights = HittableList::new();
+    for (i, object) in description.objects.iter().enumerate() {
+        let path = format!("objects[{}]", i);
+        if object.light && !object.can_be_sampled() {
+            return Err(SceneError::new(
+                format!("{}.light", path),
+                "moving objects and volumes can't be lights",
+            ));
+        }
+
+        let hittable = builder.object(object, &path)?;
+        if object.light {
+            lights.add(hittable.clone());
+        }
+        objects.push(hittable);
+    }
+
+    if objects.is_empty() {
+        return Err(SceneError::new("objects", "scene has no objects"));
+    }
+
+    let render = &description.render;
+    let camera = &description.camera;
+
+    let (image_width, samples_per_pixel, max_depth) = match (preset, &render.precise) {
+        (Preset::Precise, Some(precise)) => (
+            precise.image_width,
+            precise.samples_per_pixel,
+            precise.max_depth,
+        ),
+        _ => (
+            render.image_width,
+            render.samples_per_pixel,
+            render.max_depth,
+        ),
+    };
+
+    let background = match &description.background {
+        BackgroundDescription::Color(color) => Background::Color(to_vec3(*color)),
+        BackgroundDescription::Sky { sky } => Background::Sky {
+            horizon: to_vec3(sky.horizon),
+            zenith: to_vec3(sky.zenith),
+        },
+    };
+
+    let mut camera = Camera::new(
+        to_vec3(camera.look_from),
+        to_vec3(camera.look_at),
+        to_vec3(camera.v_up),
+        camera.vfov,
+        render.aspect_ratio,
+        camera.aperture,
+        camera.focus_dist,
+    );
+    camera.set_shutter(description.camera.shutter[0], description.camera.shutter[1]);
+
+    Ok(CPURenderingParams {
+        world: LinearBVH::new(&ObjectId::number(&objects), 0.0, f32::MAX),
+        camera,
+        image_width,
+        samples_per_pixel,
+        max_depth,
+        roulette_depth: render.roulette_depth,
+        aspect_ratio: render.aspect_ratio,
+        path: render.path.clone().unwrap_or_default(),
+        format: render.format,
+        display: DisplaySettings {
+            tone_mapping: render.tone_mapping,
+            exposure: render.exposure,
+        },
+        background,
+        lights,
+        integrator: render.integrator,
+        sampler: render.sampler,
+        filter: Filter::new(render.filter, render.filter_radius),
+        progressive: None,
+        adaptive: render.adaptive.clone(),
+        aovs: render.aovs.clone(),
+        denoise: render.denoise.clone(),
+        tile_size: DEFAULT_TILE_SIZE,
+        observer: None,
+        seed: None,
+    })
+}
+
+/// Default output file for a scene, precise renders get their own file.
+pub fn output_path(name: &str, preset: Preset) -> String {
+    match preset {
+        Preset::Fast => format!("{}.bmp", name),
+        Preset::Precise => format!("{}_precise.bmp", name),
+    }
+}
+
+/// What makes two objects load the same triangles: the file, canonicalized so
+/// different spellings of its path match, and the options it is loaded with.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum MeshKey {
+    Mesh {
+        path: PathBuf,
+        name: Option<String>,
+        material: String,
+        normals: NormalMode,
+    },
+    Gltf {
+        path: PathBuf,
+        material: Option<String>,
+    },
+    Obj {
+        path: PathBuf,
+        material: Option<String>,
+        normals: NormalMode,
+    },
+}
+
+struct SceneBuilder<'a> {
+    description: &'a SceneDescription,
+    base_dir: PathBuf,
+    textures: HashMap<String, Arc<dyn Texture>>,
+    materials: HashMap<String, Arc<dyn Material>>,
+    resolving: HashSet<String>,
+    /// Loaded mesh, obj and gltf files, shared by the objects that repeat them.
+    meshes: HashMap<MeshKey, Arc<dyn Hittable>>,
+}
+
+impl<'a> SceneBuilder<'a> {
+    fn new(description: &'a SceneDescription, base_dir: &Path) -> Self {
+        Self {
+            description,
+            base_dir: base_dir.to_path_buf(),
+            textures: HashMap::new(),
+            materials: HashMap::new(),
+            resolving: HashSet::new(),
+            meshes: HashMap::new(),
+        }
+    }
+
+    fn texture_reference(
+        &mut self,
+        reference: &TextureReference,
+        path: &str,
+    ) -> Result<Arc<dyn Texture>, SceneError> {
+        match reference {
+            TextureReference::Color(color) => Ok(SolidColor::new(to_vec3(*color))),
+            TextureReference::Named(name) => self.texture(name, path),
+        }
+    }
+
+    fn texture(&mut self, name: &str, path: &str) -> Result<Arc<dyn Texture>, SceneError> {
+        if let Some(texture) = self.textures.get(name) {
+            return Ok(texture.clone());
+        }
+
+        let description = self
+            .description
+            .textures
+            .get(name)
+            .ok_or_else(|| SceneError::new(path, format!("unknown texture `{}`", name)))?;
+
+        if !self.resolving.insert(name.to_string()) {
+            return Err(SceneError::new(
+                path,
+                format!("texture `{}` references itself", name),
+            ));
+        }
+
+        let texture_path = format!("textures.{}", name);
+        let texture: Arc<dyn Texture> = match description {
+            TextureDescription::Solid { color } => SolidColor::new(to_vec3(*color)),
+            TextureDescription::Checker { even, odd, size } => CheckerTexture::new(
+                self.texture_reference(even, &format!("{}.even", texture_path))?,
+                self.texture_reference(odd, &format!("{}.odd", texture_path))?,
+                *size,
+            ),
+            TextureDescription::DebugUv => DebugUVTexture::new(),
+            TextureDescription::Noise { noise } => NoiseTexture::new(
+                noise.seed,
+                noise.scale,
+                to_vec3(noise.low),
+                to_vec3(noise.high),
+            ),
+            TextureDescription::Turbulence { noise, depth } => TurbulenceTexture::new(
+                noise.seed,
+                noise.scale,
+                *depth,
+                to_vec3(noise.low),
+                to_vec3(noise.high),
+            ),
+            TextureDescription::Fbm {
+                noise,
+                octaves,
+                lacunarity,
+                gain,
+            } => FbmTexture::new(
+                noise.seed,
+                noise.scale,
+                *octaves,
+                *lacunarity,
+                *gain,
+                to_vec3(noise.low),
+                to_vec3(noise.high),
+            ),
+            TextureDescription::Marble { noise, depth } => MarbleTexture::new(
+                noise.seed,
+                noise.scale,
+                *depth,
+                to_vec3(noise.low),
+                to_vec3(noise.high),
+            ),
+            TextureDescription::Wood { noise, depth } => WoodTexture::new(
+                noise.seed,
+                noise.scale,
+                *depth,
+                to_vec3(noise.low),
+                to_vec3(noise.high),
+            ),
+            TextureDescription::Image {
+                path: image_path,
+                filtering,
+                flip,
+                repeating,
+            } => {
+                let full_path = self.base_dir.join(image_path);
+                ImageTexture::open(
+                    &full_path.to_string_lossy(),
+                    *filtering,
+                    *flip,
+                    Vec2::new(repeating[0], repeating[1]),
+                )
+                .map_err(|e| {
+                    SceneError::new(
+                        format!("{}.path", texture_path),
+                        format!("{}: {}", full_path.display(), e),
+                    )
+                })?
+            }
+        };
+
+        self.resolving.remove(name);
+        self.textures.insert(name.to_string(), texture.clone());
+
+        Ok(texture)
+    }
+
+    fn material(&mut self, name: &str, path: &str) -> Result<Arc<dyn Material>, SceneError> {
+        if let Some(material) = self.materials.get(name) {
+            return Ok(material.clone());
+        }
+
+        let description = self
+            .description
+            .materials
+            .get(name)
+            .ok_or_else(|| SceneError::new(path, format!("unknown material `{}`", name)))?;
+
+        let material: Arc<dyn Material> = match description {
+            MaterialDescription::Lambertian { albedo } => Lambertian::new(
+                self.texture_reference(albedo, &format!("materials.{}.albedo", name))?,
+            ),
+            MaterialDescription::Metal { albedo, fuzz } => Metal::new(to_vec3(*albedo), *fuzz),
+            MaterialDescription::Dielectric {
+                index_of_refraction,
+            } => Dielectric::new(*index_of_refraction),
+            MaterialDescription::DiffuseLight { color, side } => {
+                DiffuseLight::new(to_vec3(*color)).set_side(*side).arc()
+            }
+            MaterialDescription::Isotropic { albedo } => Isotropic::new(
+                self.texture_reference(albedo, &format!("materials.{}.albedo", name))?,
+            ),
+            MaterialDescription::Debug { target } => DebugMaterial::new(*target).arc(),
+        };
+
+        self.materials.insert(name.to_string(), material.clone());
+
+        Ok(material)
+    }
+
+    /// Key the mesh, obj and gltf shapes are shared under, none for the others.
+    fn mesh_key(&self, shape: &ShapeDescription) -> Option<MeshKey> {
+        // a file that can't be found fails to load anyway
+        let file = |path: &str| {
+            let path = self.base_dir.join(path);
+            fs::canonicalize(&path).unwrap_or(path)
+        };
+
+        match shape {
+            ShapeDescription::Mesh {
+                path,
+                name,
+                material,
+                normals,
+            } => Some(MeshKey::Mesh {
+                path: file(path),
+                name: name.clone(),
+                material: material.clone(),
+                normals: *normals,
+            }),
+            ShapeDescription::Gltf { path, material } => Some(MeshKey::Gltf {
+                path: file(path),
+                material: material.clone(),
+            }),
+            ShapeDescription::Obj {
+                path,
+                material,
+                normals,
+            } => Some(MeshKey::Obj {
+                path: file(path),
+                material: material.clone(),
+                normals: *normals,
+            }),
+            _ => None,
+        }
+    }
+
+    fn object(
+        &mut self,
+        object: &ObjectDescription,
+        path: &str,
+    ) -> Result<Arc<dyn Hittable>, SceneError> {
+        let mesh_key = self.mesh_key(&object.shape);
+
+        let cached = mesh_key.as_ref().and_then(|key| self.meshes.get(key));
+
+        let mut hittable: Arc<dyn Hittable> = match cached {
+            Some(cached) => cached.clone(),
+            None => self.shape(&object.shape, path)?,
+        };
+
+        if let Some(key) = mesh_key {
+            self.meshes.insert(key, hittable.clone());
+        }
+
+        // affine transforms in a row are merged into a single instance
+        let mut pending: Option<na::Matrix4<f32>> = None;
+
+        for (i, transform) in object.transforms.iter().enumerate() {
+            let matrix = match transform {
+                TransformDescription::Translate(displacement) => {
+                    hittable = instance(hittable, pending.take());
+                    hittable = Translate::new(hittable, to_vec3(*displacement)).arc();
+                    continue;
+                }
+                TransformDescription::RotateY(angle) => {
+                    hittable = instance(hittable, pending.take());
+                    hittable = RotateY::new(hittable, *angle).arc();
+                    continue;
+                }
+                TransformDescription::Keyframes(keyframes) => {
+                    if keyframes.is_empty() {
+                        return Err(SceneError::new(
+                            format!("{}.transforms[{}].keyframes", path, i),
+                            "no keyframes",
+                        ));
+                    }
+
+                    let keyframes = keyframes
+                        .iter()
+                        .map(|k| Keyframe {
+                            time: k.time,
+                            translation: to_vec3(k.translate),
+                            rotation: na::UnitQuaternion::from_euler_angles(
+                                k.rotate[0].to_radians(),
+                                k.rotate[1].to_radians(),
+                                k.rotate[2].to_radians(),
+                            ),
+                            scale: k.scale.to_vec3(),
+                        })
+                        .collect();
+
+                    hittable = instance(hittable, pending.take());
+                    hittable = Animated::new(hittable, keyframes).arc();
+                    continue;
+                }
+                TransformDescription::RotateX(angle) => {
+                    na::Matrix4::from_euler_angles(angle.to_radians(), 0.0, 0.0)
+                }
+                TransformDescription::RotateZ(angle) => {
+                    na::Matrix4::from_euler_angles(0.0, 0.0, angle.to_radians())
+                }
+                TransformDescription::Scale(scale) => {
+                    na::Matrix4::new_nonuniform_scaling(&scale.to_vec3().into())
+                }
+                TransformDescription::Matrix(rows) => {
+                    na::Matrix4::from_fn(|row, column| rows[row][column])
+                }
+            };
+
+            let combined = matrix * pending.unwrap_or_else(na::Matrix4::identity);
+
+            if !combined.is_invertible() {
+                return Err(SceneError::new(
+                    format!("{}.transforms[{}]", path, i),
+                    "transform is not invertible",
+                ));
+            }
+
+            pending = Some(combined);
+        }
+
+        Ok(instance(hittable, pending))
+    }
+
+    /// Objects inside groups and volumes, which are never sampled as lights.
+    fn nested_object(
+        &mut self,
+        object: &ObjectDescription,
+        path: &str,
+    ) -> Result<Arc<dyn Hittable>, SceneError> {
+        if object.light {
+            return Err(SceneError::new(
+                format!("{}.light", path),
+                "only top level objects can be lights",
+            ));
+        }
+
+        self.object(object, path)
+    }
+
+    fn shape(
+        &mut self,
+        shape: &ShapeDescription,
+        path: &str,
+    ) -> Result<Arc<dyn Hittable>, SceneError> {
+        let material_path = format!("{}.material", path);
+
+        let hittable: Arc<dyn Hittable> = match shape {
+            ShapeDescription::Sphere {
+                center,
+                radius,
+                material,
+            } => Sphere::new(
+                to_vec3(*center),
+                *radius,
+                self.material(material, &material_path)?,
+            ),
+            ShapeDescription::MovingSphere {
+                center0,
+                center1,
+                time0,
+                time1,
+                radius,
+                material,
+            } => MovingSphere::new(
+                to_vec3(*center0),
+                to_vec3(*center1),
+                *time0,
+                *time1,
+                *radius,
+                self.material(material, &material_path)?,
+            ),
+            ShapeDescription::Triangle {
+                position,
+                normal,
+                uv,
+                material,
+            } => {
+                let position = Attribute::new(
+                    to_vec3(position[0]),
+                    to_vec3(position[1]),
+                    to_vec3(position[2]),
+                );
+                let normal = match normal {
+                    Some(n) => Attribute::new(to_vec3(n[0]), to_vec3(n[1]), to_vec3(n[2])),
+                    None => {
+                        let n = (position.b - position.a)
+                            .cross(position.c - position.a)
+                            .unit_vector();
+                        Attribute::new(n, n, n)
+                    }
+                };
+                let uv = match uv {
+                    Some(t) => Attribute::new(
+                        Vec2::new(t[0][0], t[0][1]),
+                        Vec2::new(t[1][0], t[1][1]),
+                        Vec2::new(t[2][0], t[2][1]),
+                    ),
+                    None => Attribute::new(
+                        Vec2::new(0.0, 0.0),
+                        Vec2::new(1.0, 0.0),
+                        Vec2::new(0.0, 1.0),
+                    ),
+                };
+
+                Triangle::new(
+                    position,
+                    normal,
+                    uv,
+                    self.material(material, &material_path)?,
+                )
+            }
+            ShapeDescription::XyRect {
+                x0,
+                x1,
+                y0,
+                y1,
+                k,
+                material,
+            } => xy_rect(
+                *x0,
+                *x1,
+                *y0,
+                *y1,
+                *k,
+                self.material(material, &material_path)?,
+            ),
+            ShapeDescription::YzRect {
+                y0,
+                y1,
+                z0,
+                z1,
+                k,
+                material,
+            } => yz_rect(
+                *y0,
+                *y1,
+                *z0,
+                *z1,
+                *k,
+                self.material(material, &material_path)?,
+            ),
+            ShapeDescription::XzRect {
+                x0,
+                x1,
+                z0,
+                z1,
+                k,
+                material,
+            } => xz_rect(
+                *x0,
+                *x1,
+                *z0,
+                *z1,
+                *k,
+                self.material(material, &material_path)?,
+            ),
+            ShapeDescription::Box { p0, p1, material } => bake_box(
+                to_vec3(*p0),
+                to_vec3(*p1),
+                self.material(material, &material_path)?,
+            ),
+            ShapeDescription::Mesh {
+                path: mesh_path,
+                name,
+                material,
+                normals,
+            } => {
+                let material = self.material(material, &material_path)?;
+                let options = JsonMeshOptions {
+                    name: name.clone(),
+                    normals: *normals,
+                    ..JsonMeshOptions::default()
+                };
+
+                load_json_mesh(self.base_dir.join(mesh_path), material, &options)
+                    .map_err(|e| SceneError::new(format!("{}.path", path), e.to_string()))?
+            }
+            ShapeDescription::Gltf {
+                path: gltf_path,
+                material,
+            } => {
+                let material = match material {
+                    Some(material) => Some(self.material(material, &material_path)?),
+                    None => None,
+                };
+
+                load_gltf(self.base_dir.join(gltf_path), material)
+                    .map_err(|e| SceneError::new(format!("{}.path", path), e.to_string()))?
+                    .world
+            }
+            ShapeDescription::Obj {
+                path: obj_path,
+                material,
+                normals,
+            } => {
+                let material = match material {
+                    Some(material) => Some(self.material(material, &material_path)?),
+                    None => None,
+                };
+                let options = ObjOptions {
+                    normals: *normals,
+                    material,
+                };
+
+                load_obj(self.base_dir.join(obj_path), &options)
+                    .map_err(|e| SceneError::new(format!("{}.path", path), e.to_string()))?
+            }
+            ShapeDescription::ConstantMedium {
+                boundary,
+                density,
+                albedo,
+            } => {
+                if *density <= 0.0 {
+                    return Err(SceneError::new(
+                        format!("{}.density", path),
+                        "density must be positive",
+                    ));
+                }
+
+                ConstantMedium::new(
+                    self.nested_object(boundary, &format!("{}.boundary", path))?,
+                    *density,
+                    self.texture_reference(albedo, &format!("{}.albedo", path))?,
+                )
+            }
+            ShapeDescription::GridVolume {
+                boundary,
+                grid,
+                density,
+                albedo,
+            } => {
+                if *density <= 0.0 {
+                    return Err(SceneError::new(
+                        format!("{}.density", path),
+                        "density must be positive",
+                    ));
+                }
+
+                let grid_path = format!("{}.grid", path);
+                let grid = match grid {
+                    GridDescription::Raw {
+                        path: raw_path,
+                        dims,
+                    } => {
+                        if dims.contains(&0) {
+                            return Err(SceneError::new(
+                                format!("{}.dims", grid_path),
+                                "grid dimensions must be positive",
+                            ));
+                        }
+
+                        DensityGrid::load_raw(self.base_dir.join(raw_path), *dims)
+                            .map_err(|e| SceneError::new(&grid_path, e.to_string()))?
+                    }
+                    GridDescription::Sphere { resolution } => {
+                        if *resolution == 0 {
+                            return Err(SceneError::new(
+                                format!("{}.resolution", grid_path),
+                                "resolution must be positive",
+                            ));
+                        }
+
+                        DensityGrid::from_fn([*resolution; 3], |p| {
+                            let r = (p - Point3::new(0.5, 0.5, 0.5)).length() * 2.0;
+                            (1.0 - r).max(0.0).powi(2)
+                        })
+                    }
+                };
+
+                let boundary_path = format!("{}.boundary", path);
+                GridVolume::new(
+                    self.nested_object(boundary, &boundary_path)?,
+                    grid,
+                    *density,
+                    self.texture_reference(albedo, &format!("{}.albedo", path))?,
+                )
+                .map_err(|e| SceneError::new(&boundary_path, e.to_string()))?
+            }
+            ShapeDescription::Group { objects } => {
+                let mut children = vec![];
+                for (i, child) in objects.iter().enumerate() {
+                    children.push(self.nested_object(child, &format!("{}.objects[{}]", path, i))?);
+                }
+
+                if children.is_empty() {
+                    return Err(SceneError::new(
+                        format!("{}.objects", path),
+                        "group has no objects",
+                    ));
+                }
+
+                Arc::new(LinearBVH::new(&children, 0.0, f32::MAX))
+            }
+        };
+
+        Ok(hittable)
+    }
+}
+
+fn instance(hittable: Arc<dyn Hittable>, transform: Option<na::Matrix4<f32>>) -> Arc<dyn Hittable> {
+    match transform {
+        Some(transform) => Instance::new(hittable, transform).arc(),
+        None => hittable,
+    }
+}
+
+fn to_vec3(v: Vec3Description) -> Vec3 {
+    Vec3::new(v[0], v[1], v[2])
+}
+
+fn default_v_up() -> Vec3Description {
+    [0.0, 1.0, 0.0]
+}
+
+fn default_noise_scale() -> f32 {
+    1.0
+}
+
+fn default_high() -> Vec3Description {
+    [1.0, 1.0, 1.0]
+}
+
+fn default_depth() -> usize {
+    7
+}
+
+fn default_octaves() -> usize {
+    6
+}
+
+fn default_lacunarity() -> f32 {
+    2.0
+}
+
+fn default_gain() -> f32 {
+    0.5
+}
+
+fn default_time1() -> f32 {
+    1.0
+}
+
+fn default_scale() -> ScaleDescription {
+    ScaleDescription::Uniform(1.0)
+}
+
+fn default_focus_dist() -> f32 {
+    10.0
+}
+
+fn default_filtering() -> TextureFiltering {
+    TextureFiltering::Linear
+}
+
+fn default_flip() -> TextureFlip {
+    TextureFlip::AsIs
+}
+
+fn default_repeating() -> Vec2Description {
+    [1.0, 1.0]
+}
+
+fn default_side() -> Side {
+    Side::Double
+}
+
+fn default_normals() -> NormalMode {
+    NormalMode::Smooth
+}
@@ -0,0 +1,330 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use nalgebra as na;
+use rand::prelude::*;
+
+use crate::common::*;
+use crate::cpu_renderer::*;
+use crate::progress::*;
+use crate::scene::*;
+
+/// Resolves a scene by name: the procedural `one_weekend` and `next_week` scenes,
+/// a path to a scene or glTF file, or the name of a file in `./assets/scenes`.
+pub fn load_named_scene(
+    name: &str,
+    preset: Preset,
+    seed: Option<u64>,
+) -> Result<CPURenderingParams<LinearBVH>, SceneError> {
+    // the procedural scenes are made from the seed, which a resumed render needs
+    // to make them again, so they always get one
+    let procedural_seed = || seed.unwrap_or_else(|| thread_rng().gen());
+
+    let (mut params, seed) = match name {
+        "one_weekend" => {
+            let seed = procedural_seed();
+            let params = one_weekend(&mut StdRng::seed_from_u64(seed), preset);
+            (params, Some(seed))
+        }
+        "next_week" => {
+            let seed = procedural_seed();
+            let params = next_week(&mut StdRng::seed_from_u64(seed), preset)?;
+            (params, Some(seed))
+        }
+        _ => match scene_file(name) {
+            Some(path) if is_gltf(&path) => (gltf_scene(&path, preset)?, seed),
+            Some(path) => (load_scene(path, preset)?, seed),
+            None => return Err(SceneError::new("", format!("unknown scene {}", name))),
+        },
+    };
+
+    params.seed = seed;
+
+    Ok(params)
+}
+
+/// File a scene name that isn't procedural refers to: a path to a scene or glTF
+/// file, or the name of a file in `./assets/scenes`.
+pub fn scene_file(name: &str) -> Option<String> {
+    if name.ends_with(".json") || is_gltf(name) {
+        return Some(name.to_string());
+    }
+
+    let path = format!("./assets/scenes/{}.json", name);
+    if Path::new(&path).exists() {
+        Some(path)
+    } else {
+        None
+    }
+}
+
+fn is_gltf(path: &str) -> bool {
+    path.ends_with(".gltf") || path.ends_with(".glb")
+}
+
+pub fn one_weekend<R: Rng>(rng: &mut R, preset: Preset) -> CPURenderingParams<LinearBVH> {
+    let (image_width, samples_per_pixel, max_depth) = match preset {
+        Preset::Fast => (800, 30, 30),
+        Preset::Precise => (1920, 1000, 50),
+    };
+    let aspect_ratio = 16.0 / 9.0;
+
+    let mut objects: Vec<Arc<dyn Hittable>> = vec![];
+    let ground_texture = SolidColor::new(Color::new(0.5, 0.5, 0.5));
+    let ground_material = Lambertian::new(ground_texture);
+    objects.push(Sphere::new(
+        Point3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        ground_material,
+    ));
+
+    for a in -11..11 {
+        for b in -11..11 {
+            let choose_mat: f32 = rng.gen();
+
+            let center = Point3::new(
+                a as f32 + 0.9 * rng.gen::<f32>(),
+                0.2,
+                b as f32 + 0.9 * rng.gen::<f32>(),
+            );
+
+            if (center - Point3::new(4.0, 0.2, 0.0)).length() > 0.9 {
+                let material: Arc<dyn Material> = if choose_mat < 0.8 {
+                    // diffuse
+                    let albedo = random_color(rng, 0.0, 1.0) * random_color(rng, 0.0, 1.0);
+                    Lambertian::new(SolidColor::new(albedo))
+                } else if choose_mat < 0.95 {
+                    // metal
+                    let albedo = random_color(rng, 0.5, 1.0);
+                    let fuzz = rng.gen_range(0.0..0.5);
+                    Metal::new(albedo, fuzz)
+                } else {
+                    // glass
+                    Dielectric::new(1.5)
+                };
+                objects.push(Sphere::new(center, 0.2, material));
+            }
+        }
+    }
+
+    let m1 = Dielectric::new(1.5);
+    objects.push(Sphere::new(Point3::new(0.0, 1.0, 0.0), 1.0, m1));
+
+    let m2 = Lambertian::new(SolidColor::new(Color::new(0.4, 0.2, 0.1)));
+    objects.push(Sphere::new(Point3::new(-4.0, 1.0, 0.0), 1.0, m2));
+
+    let m3 = Metal::new(Color::new(0.7, 0.6, 0.5), 0.0);
+    objects.push(Sphere::new(Point3::new(4.0, 1.0, 0.0), 1.0, m3));
+
+    CPURenderingParams {
+        world: LinearBVH::new(&ObjectId::number(&objects), 0.0, f32::MAX),
+        camera: Camera::new(
+            Point3::new(13.0, 2.0, 3.0),
+            Point3::new(0.0, 0.0, -1.0),
+            Point3::new(0.0, 0.1, 0.0),
+            20.0,
+            aspect_ratio,
+            0.1,
+            10.0,
+        ),
+        image_width,
+        samples_per_pixel,
+        max_depth,
+        roulette_depth: DEFAULT_ROULETTE_DEPTH,
+        aspect_ratio,
+        path: output_path("one_weekend", preset),
+        format: None,
+        display: DisplaySettings::default(),
+        background: Background::Sky {
+            horizon: Color::new(1.0, 1.0, 1.0),
+            zenith: Color::new(0.5, 0.7, 1.0),
+        },
+        lights: HittableList::new(),
+        integrator: Integrator::default(),
+        sampler: SamplerKind::default(),
+        filter: Filter::default(),
+        progressive: None,
+        adaptive: None,
+        aovs: vec![],
+        denoise: None,
+        tile_size: DEFAULT_TILE_SIZE,
+        observer: None,
+        seed: None,
+    }
+}
+
+pub fn next_week<R: Rng>(
+    rng: &mut R,
+    preset: Preset,
+) -> Result<CPURenderingParams<LinearBVH>, SceneError> {
+    let (image_width, samples_per_pixel, max_depth) = match preset {
+        Preset::Fast => (800, 30, 30),
+        Preset::Precise => (1920, 2000, 100),
+    };
+    let aspect_ratio = 16.0 / 9.0;
+
+    let mut objects: Vec<Arc<dyn Hittable>> = vec![];
+    let texture_path = "./assets/bricks.jpeg";
+    let image_texture = ImageTexture::open(
+        texture_path,
+        TextureFiltering::Linear,
+        TextureFlip::FlipY,
+        Vec2::new(0.5, 0.5),
+    )
+    .map_err(|e| SceneError::new("", format!("{}: {}", texture_path, e)))?;
+
+    let ground_texture = SolidColor::new(Color::new(0.5, 0.5, 0.5));
+    let ground_material = Lambertian::new(ground_texture);
+
+    objects.push(Sphere::new(
+        Point3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        ground_material,
+    ));
+
+    // light plane
+    let light = yz_rect(
+        0.5,
+        1.5,
+        -0.5,
+        0.5,
+        2.0,
+        DiffuseLight::new(Color::new(1.0, 1.0, 1.0) * 4.0).arc(),
+    );
+    let mut lights = HittableList::new();
+    lights.add(light.clone());
+    objects.push(light);
+
+    for a in -11..11 {
+        for b in -11..11 {
+            let choose_mat: f32 = rng.gen();
+
+            let mut center = Point3::new(
+                a as f32 + 0.9 * rng.gen::<f32>(),
+                0.2,
+                b as f32 + 0.9 * rng.gen::<f32>(),
+            );
+
+            if center.length() < 1.0 {
+                center.x *= 2.0;
+                center.z *= 2.0;
+            }
+
+            if (center - Point3::new(4.0, 0.2, 0.0)).length() > 0.9 {
+                let material: Arc<dyn Material> = if choose_mat < 0.8 {
+                    // diffuse
+                    Lambertian::new(image_texture.clone())
+                } else {
+                    // light
+                    DiffuseLight::new(Color::new(1.0, 1.0, 1.0)).arc()
+                };
+                objects.push(Sphere::new(center, 0.2, material));
+            }
+        }
+    }
+
+    let cup_color1 = SolidColor::new(Color::new(0.3, 0.8, 0.6));
+    let cup_color2 = SolidColor::new(Color::new(0.9, 0.9, 0.9));
+
+    let cup_texture = CheckerTexture::new(cup_color1, cup_color2, 200.0);
+    let cup_material = Lambertian::new(cup_texture);
+
+    let monkey_path = "./assets/monkey.json";
+    let monkey_options = JsonMeshOptions {
+        transform: na::Matrix4::new_translation(&na::Vector3::new(0.0, 1.0, 0.0)),
+        ..JsonMeshOptions::default()
+    };
+    objects.push(
+        load_json_mesh(monkey_path, cup_material, &monkey_options)
+            .map_err(|e| SceneError::new("", e.to_string()))?,
+    );
+
+    Ok(CPURenderingParams {
+        world: LinearBVH::new(&ObjectId::number(&objects), 0.0, f32::MAX),
+        camera: Camera::new(
+            Point3::new(0.0, 4.0, 10.0),
+            Point3::new(0.0, 0.0, -3.0),
+            Point3::new(0.0, 1.0, 0.0),
+            20.0,
+            aspect_ratio,
+            0.1,
+            10.0,
+        ),
+        image_width,
+        samples_per_pixel,
+        max_depth,
+        roulette_depth: DEFAULT_ROULETTE_DEPTH,
+        aspect_ratio,
+        path: output_path("next_week", preset),
+        format: None,
+        display: DisplaySettings::default(),
+        background: Background::Color(Color::new(0.0, 0.0, 0.0)),
+        lights,
+        integrator: Integrator::default(),
+        sampler: SamplerKind::default(),
+        filter: Filter::default(),
+        progressive: None,
+        adaptive: None,
+        aovs: vec![],
+        denoise: None,
+        tile_size: DEFAULT_TILE_SIZE,
+        observer: None,
+        seed: None,
+    })
+}
+
+/// Renders a glTF file through its first camera under a sky, as exported files
+/// rarely contain emissive geometry.
+pub fn gltf_scene(path: &str, preset: Preset) -> Result<CPURenderingParams<LinearBVH>, SceneError> {
+    let (image_width, samples_per_pixel, max_depth) = match preset {
+        Preset::Fast => (400, 100, 50),
+        Preset::Precise => (1920, 1000, 100),
+    };
+
+    let scene = load_gltf(path, None).map_err(|e| SceneError::new("", e.to_string()))?;
+    let camera = scene
+        .cameras
+        .first()
+        .ok_or_else(|| SceneError::new("", format!("{} has no perspective camera", path)))?;
+    let aspect_ratio = camera.aspect_ratio.unwrap_or(16.0 / 9.0);
+    let name = Path::new(path)
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or("gltf");
+
+    Ok(CPURenderingParams {
+        world: LinearBVH::new(&[scene.world], 0.0, f32::MAX),
+        camera: camera.camera(aspect_ratio),
+        image_width,
+        samples_per_pixel,
+        max_depth,
+        roulette_depth: DEFAULT_ROULETTE_DEPTH,
+        aspect_ratio,
+        path: output_path(name, preset),
+        format: None,
+        display: DisplaySettings::default(),
+        background: Background::Sky {
+            horizon: Color::new(1.0, 1.0, 1.0),
+            zenith: Color::new(0.5, 0.7, 1.0),
+        },
+        lights: HittableList::new(),
+        integrator: Integrator::default(),
+        sampler: SamplerKind::default(),
+        filter: Filter::default(),
+        progressive: None,
+        adaptive: None,
+        aovs: vec![],
+        denoise: None,
+        tile_size: DEFAULT_TILE_SIZE,
+        observer: None,
+        seed: None,
+    })
+}
+
+fn random_color<R: Rng>(rng: &mut R, from: f32, to: f32) -> Color {
+    Color::new(
+        rng.gen_range(from..to),
+        rng.gen_range(from..to),
+        rng.gen_range(from..to),
+    )
+}